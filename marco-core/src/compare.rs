@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, LazyLock};
+
+use regex::Regex;
+
+use crate::types::{Compare, MarcoTestCase};
+use crate::util::{
+    JsonPath, hexdump, is_json, match_regex_lines, normalize_newlines, normalized_json_eq,
+    normalized_yaml_eq, pretty_json, pretty_yaml, sha256_hex,
+};
+
+/// Decimal numbers, optionally signed and in scientific notation (e.g. `-1.5e-3`)
+static NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[-+]?(?:\d+\.?\d*|\.\d+)(?:[eE][-+]?\d+)?").unwrap());
+
+/// Absolute tolerance of `compare: numeric` when the header sets neither tolerance
+const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// What a comparator is given of a test run
+pub struct CompareInput<'a> {
+    pub test: &'a MarcoTestCase,
+    /// Content of the Expected Output block, normalized like the output and trimmed
+    pub expected: &'a str,
+    /// Output as it is compared: normalized, trimmed, and with stderr for `expect_failure` tests
+    pub actual: &'a str,
+    /// Bytes the runner wrote to stdout, as they are
+    pub stdout: &'a [u8],
+    /// The test's parsed `ignore_paths`
+    pub ignore_paths: &'a [JsonPath],
+}
+
+/// Outcome of a comparison
+#[derive(Debug)]
+pub struct Comparison {
+    pub matched: bool,
+    /// (actual, expected) as the diff of a mismatch shows them, if not the compared text,
+    /// e.g. pretty printed JSON
+    pub shown: Option<(String, String)>,
+}
+
+impl Comparison {
+    pub fn of(matched: bool) -> Self {
+        Comparison {
+            matched,
+            shown: None,
+        }
+    }
+
+    /// A comparison whose diff shows `expected` instead of the compared Expected Output
+    fn showing_expected(matched: bool, input: &CompareInput, expected: String) -> Self {
+        Comparison {
+            matched,
+            shown: (!matched).then(|| (input.actual.to_string(), expected)),
+        }
+    }
+}
+
+/// A strategy for deciding whether a test's output matches its Expected Output block
+pub trait Comparator: Send + Sync {
+    /// Compares the output with the Expected Output. An error means the test itself is invalid,
+    /// e.g. its regex does not compile, and fails it without a diff.
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String>;
+}
+
+/// Comparators by the name a test's `compare` header selects them with, starting with the
+/// built-in ones; registering a name again replaces its comparator
+#[derive(Clone)]
+pub struct Comparators(BTreeMap<String, Arc<dyn Comparator>>);
+
+impl Default for Comparators {
+    fn default() -> Self {
+        let mut comparators = Comparators(BTreeMap::new());
+        comparators.register("exact", ExactComparator);
+        comparators.register("json", JsonComparator);
+        comparators.register("yaml", YamlComparator);
+        comparators.register("toml", TomlComparator);
+        comparators.register("numeric", NumericComparator);
+        comparators.register("unordered-lines", UnorderedLinesComparator);
+        comparators.register("regex", RegexComparator);
+        comparators.register("bytes", BytesComparator);
+        comparators.register("sha256", Sha256Comparator);
+        comparators
+    }
+}
+
+impl fmt::Debug for Comparators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl Comparators {
+    pub fn register(&mut self, name: impl Into<String>, comparator: impl Comparator + 'static) {
+        self.0.insert(name.into(), Arc::new(comparator));
+    }
+
+    /// The comparator for a test: ```` ```hex ````/```` ```base64 ```` and ```` ```sha256 ````
+    /// blocks always compare raw bytes, ```` ```regex ```` and ```` ```yaml ```` blocks select
+    /// their comparator unless the header sets `compare`, and `compare` does otherwise
+    pub fn select(&self, test: &MarcoTestCase) -> Result<&dyn Comparator, String> {
+        let name = if test.expected_bytes.is_some() {
+            "bytes"
+        } else if test.expected_sha256.is_some() {
+            "sha256"
+        } else if test.header.compare == Compare::Exact && test.expected_regex {
+            "regex"
+        } else if test.header.compare == Compare::Exact && test.expected_yaml {
+            "yaml"
+        } else {
+            test.header.compare.name()
+        };
+        self.get(name)
+    }
+
+    /// The comparator for an Expected File: a `.json`, `.yaml`/`.yml` or `.toml` extension
+    /// selects the comparator for that format unless the header sets `compare`, which does
+    /// otherwise
+    pub fn select_file(&self, test: &MarcoTestCase, path: &str) -> Result<&dyn Comparator, String> {
+        let extension = path
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase());
+        let name = match extension.as_deref() {
+            Some("json") if test.header.compare == Compare::Exact => "json",
+            Some("yaml" | "yml") if test.header.compare == Compare::Exact => "yaml",
+            Some("toml") if test.header.compare == Compare::Exact => "toml",
+            _ => test.header.compare.name(),
+        };
+        self.get(name)
+    }
+
+    fn get(&self, name: &str) -> Result<&dyn Comparator, String> {
+        self.0.get(name).map(|c| c.as_ref()).ok_or_else(|| {
+            format!(
+                "Unknown comparator {:?} in `compare` (known: {})",
+                name,
+                self.0.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+}
+
+/// Equal text, or equal JSON values if both sides look like JSON
+pub struct ExactComparator;
+
+impl Comparator for ExactComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        if is_json(input.expected) && is_json(input.actual) {
+            return JsonComparator.compare(input);
+        }
+        Ok(Comparison::of(input.actual == input.expected))
+    }
+}
+
+/// Equal JSON values, regardless of key order and formatting, without the `ignore_paths`
+pub struct JsonComparator;
+
+impl Comparator for JsonComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let matched = normalized_json_eq(input.expected, input.actual, input.ignore_paths);
+        // Pretty print both sides so the diff is line-oriented
+        let shown = match (
+            pretty_json(input.actual, input.ignore_paths),
+            pretty_json(input.expected, input.ignore_paths),
+        ) {
+            (Some(a), Some(e)) if !matched => Some((a, e)),
+            _ => None,
+        };
+        Ok(Comparison { matched, shown })
+    }
+}
+
+/// Equal YAML documents, regardless of key order, quoting and style, without the `ignore_paths`
+pub struct YamlComparator;
+
+impl Comparator for YamlComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let matched = normalized_yaml_eq(input.expected, input.actual, input.ignore_paths);
+        let shown = match (
+            pretty_yaml(input.actual, input.ignore_paths),
+            pretty_yaml(input.expected, input.ignore_paths),
+        ) {
+            (Some(a), Some(e)) if !matched => {
+                Some((a.trim_end().to_owned(), e.trim_end().to_owned()))
+            }
+            _ => None,
+        };
+        Ok(Comparison { matched, shown })
+    }
+}
+
+/// Equal TOML documents, regardless of key order and formatting
+pub struct TomlComparator;
+
+impl Comparator for TomlComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let matched = toml_match(input.expected, input.actual)?;
+        let shown = match (pretty_toml(input.actual), pretty_toml(input.expected)) {
+            (Some(a), Some(e)) if !matched => {
+                Some((a.trim_end().to_owned(), e.trim_end().to_owned()))
+            }
+            _ => None,
+        };
+        Ok(Comparison { matched, shown })
+    }
+}
+
+/// Equal text between numbers, and numbers within the header's `tolerance`/`relative_tolerance`
+pub struct NumericComparator;
+
+impl Comparator for NumericComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let header = &input.test.header;
+        let (matched, shown) = numeric_match(
+            input.expected,
+            input.actual,
+            header.tolerance,
+            header.relative_tolerance,
+        );
+        Ok(Comparison::showing_expected(matched, input, shown))
+    }
+}
+
+/// The same lines in any order
+pub struct UnorderedLinesComparator;
+
+impl Comparator for UnorderedLinesComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let (matched, shown) = unordered_lines_match(input.expected, input.actual);
+        Ok(Comparison::showing_expected(matched, input, shown))
+    }
+}
+
+/// Every output line matching the regex on the same line of the Expected Output in full
+pub struct RegexComparator;
+
+impl Comparator for RegexComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let (matched, shown) = match_regex_lines(input.expected, input.actual)?;
+        Ok(Comparison::showing_expected(matched, input, shown))
+    }
+}
+
+/// Stdout equal to the decoded bytes of a ```` ```hex ```` or ```` ```base64 ```` block,
+/// shown as hexdumps
+pub struct BytesComparator;
+
+impl Comparator for BytesComparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let expected = input.test.expected_bytes.as_deref().unwrap_or_default();
+        let matched = input.stdout == expected;
+        Ok(Comparison {
+            matched,
+            shown: (!matched).then(|| (hexdump(input.stdout), hexdump(expected))),
+        })
+    }
+}
+
+/// Stdout hashing to the digest of a ```` ```sha256 ```` block
+pub struct Sha256Comparator;
+
+impl Comparator for Sha256Comparator {
+    fn compare(&self, input: &CompareInput) -> Result<Comparison, String> {
+        let expected = input.test.expected_sha256.clone().unwrap_or_default();
+        let actual = sha256_hex(input.stdout);
+        Ok(Comparison {
+            matched: actual == expected,
+            shown: (actual != expected).then_some((actual, expected)),
+        })
+    }
+}
+
+/// Compares two outputs as text with numbers in it: the text between numbers must be equal,
+/// and each number must be within `tolerance` of, or within `relative_tolerance` times the
+/// larger magnitude of, its counterpart. Returns whether they match, and `expected` with every
+/// number that is close enough replaced by the actual one, so a diff only shows real differences.
+pub fn numeric_match(
+    expected: &str,
+    actual: &str,
+    tolerance: Option<f64>,
+    relative_tolerance: Option<f64>,
+) -> (bool, String) {
+    let (expected, actual) = (normalize_newlines(expected), normalize_newlines(actual));
+    let (expected_text, expected_numbers) = split_numbers(&expected);
+    let (actual_text, actual_numbers) = split_numbers(&actual);
+    if expected_text != actual_text || expected_numbers.len() != actual_numbers.len() {
+        return (false, expected);
+    }
+
+    let tolerance = match (tolerance, relative_tolerance) {
+        (None, None) => Some(DEFAULT_TOLERANCE),
+        (tolerance, _) => tolerance,
+    };
+    let mut matched = true;
+    let mut shown = String::new();
+    for (i, text) in expected_text.iter().enumerate() {
+        shown.push_str(text);
+        let (Some(e), Some(a)) = (expected_numbers.get(i), actual_numbers.get(i)) else {
+            continue;
+        };
+        let close = match (e.parse::<f64>(), a.parse::<f64>()) {
+            (Ok(e), Ok(a)) => {
+                let diff = (e - a).abs();
+                tolerance.is_some_and(|t| diff <= t)
+                    || relative_tolerance.is_some_and(|r| diff <= r * e.abs().max(a.abs()))
+            }
+            _ => e == a,
+        };
+        matched &= close;
+        shown.push_str(if close { a } else { e });
+    }
+    (matched, shown)
+}
+
+/// Compares two outputs as multisets of lines. Returns whether they hold the same lines, and
+/// `expected` reordered to follow `actual` as far as they share lines, with the lines missing
+/// from `actual` at the end, so a diff only shows the lines that differ.
+pub fn unordered_lines_match(expected: &str, actual: &str) -> (bool, String) {
+    let (expected, actual) = (normalize_newlines(expected), normalize_newlines(actual));
+    let mut remaining: Vec<Option<&str>> = expected.lines().map(Some).collect();
+    let mut shown = vec![];
+    let mut matched = true;
+    for line in actual.lines() {
+        match remaining.iter_mut().find(|l| **l == Some(line)) {
+            Some(slot) => shown.push(slot.take().unwrap_or_default()),
+            None => matched = false,
+        }
+    }
+    let missing: Vec<&str> = remaining.into_iter().flatten().collect();
+    matched &= missing.is_empty();
+    shown.extend(missing);
+    (matched, shown.join("\n"))
+}
+
+/// Compares two outputs as TOML documents, so key order, quoting, whitespace and inline vs.
+/// standard tables do not matter. Returns whether they hold the same values; an Expected
+/// Output that is not TOML is an error, while actual output that is not TOML just does not match.
+pub fn toml_match(expected: &str, actual: &str) -> Result<bool, String> {
+    let expected: toml::Table = toml::from_str(expected)
+        .map_err(|e| format!("Expected Output is not valid TOML: {}", e.message()))?;
+    Ok(toml::from_str::<toml::Table>(actual).is_ok_and(|actual| actual == expected))
+}
+
+/// Prints TOML with sorted keys and standard tables. Returns None if the input is not valid TOML.
+pub fn pretty_toml(s: &str) -> Option<String> {
+    let table: toml::Table = toml::from_str(s).ok()?;
+    toml::to_string(&table).ok()
+}
+
+/// Splits `s` into the text around its numbers and the numbers themselves; the text has one
+/// more piece than there are numbers
+fn split_numbers(s: &str) -> (Vec<&str>, Vec<&str>) {
+    let mut text = vec![];
+    let mut numbers = vec![];
+    let mut last = 0;
+    for m in NUMBER.find_iter(s) {
+        text.push(&s[last..m.start()]);
+        numbers.push(m.as_str());
+        last = m.end();
+    }
+    text.push(&s[last..]);
+    (text, numbers)
+}
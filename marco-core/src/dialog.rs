@@ -0,0 +1,182 @@
+use std::process::Output;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::runner::{
+    ChildConfig, ChildOutcome, aborted, block_on, killed_output, read_pipe, spawn_async,
+    supervise_with_stderr, write_input,
+};
+use crate::util::parse_duration;
+
+/// How long an `expect:` step waits for its text unless a `timeout:` line says otherwise
+pub const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One step of a scripted interactive dialog (an Input block tagged ```` ```dialog ````)
+#[derive(Debug, Clone)]
+pub enum DialogStep {
+    /// Writes a line to the child's stdin
+    Send(String),
+    /// Waits until the child's stdout contains the text
+    Expect { text: String, timeout: Duration },
+}
+
+/// Parses a dialog script made of `send: <line>`, `expect: <text>` and `timeout: <duration>` lines.
+/// A `timeout:` line applies to every following `expect:`.
+pub fn parse_dialog(script: &str) -> Result<Vec<DialogStep>, String> {
+    let mut steps = vec![];
+    let mut timeout = DEFAULT_EXPECT_TIMEOUT;
+    for line in script.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (keyword, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("expected `send:`, `expect:` or `timeout:`, got {:?}", line))?;
+        // Only the single space after the colon is dropped, so prompts like ">>> " keep theirs
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match keyword.trim() {
+            "send" => steps.push(DialogStep::Send(value.to_string())),
+            "expect" => steps.push(DialogStep::Expect {
+                text: value.to_string(),
+                timeout,
+            }),
+            "timeout" => timeout = parse_duration(value)?,
+            other => return Err(format!("unknown dialog step {:?}", other)),
+        }
+    }
+    Ok(steps)
+}
+
+/// Runs the runner command and drives it through the dialog.
+/// On success the returned stdout only holds what the child printed after the last `expect:`,
+/// which is what the Expected Output block is compared against.
+pub fn run_dialog(
+    runner_cmd: &str,
+    steps: &[DialogStep],
+    config: &ChildConfig,
+) -> Result<ChildOutcome, String> {
+    block_on(drive(runner_cmd, steps, config))?
+}
+
+async fn drive(
+    runner_cmd: &str,
+    steps: &[DialogStep],
+    config: &ChildConfig<'_>,
+) -> Result<ChildOutcome, String> {
+    let (mut child, mut monitor) = spawn_async(runner_cmd, config)?;
+    let mut stdin = child.stdin.take();
+    // Drained from the start, so a runner writing a lot to stderr cannot fill the pipe and
+    // block before printing what the dialog waits for
+    let mut stderr = tokio::spawn(read_pipe(child.stderr.take()));
+
+    // Forward stdout in chunks so expectations can be checked as soon as output arrives
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let stdout = child.stdout.take();
+    tokio::spawn(async move {
+        let Some(mut stdout) = stdout else { return };
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = stdout.read(&mut buf).await {
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut transcript: Vec<u8> = vec![];
+    let mut cursor = 0;
+    for step in steps {
+        match step {
+            DialogStep::Send(line) => {
+                let Some(stdin) = stdin.as_mut() else {
+                    continue;
+                };
+                let written = async {
+                    stdin.write_all(format!("{}\n", line).as_bytes()).await?;
+                    stdin.flush().await
+                };
+                if let Err(e) = written.await {
+                    monitor.kill_tree_async(&mut child);
+                    let _ = monitor.wait_async(&mut child).await;
+                    stderr.abort();
+                    return Err(format!("Failed to send {:?} to the runner: {}", line, e));
+                }
+            }
+            DialogStep::Expect { text, timeout } => {
+                let until = Instant::now() + *timeout;
+                let until = config
+                    .deadline
+                    .map_or(until, |deadline| deadline.min(until));
+                loop {
+                    if let Some(pos) = find(&transcript[cursor..], text.as_bytes()) {
+                        cursor += pos + text.len();
+                        break;
+                    }
+                    let error = tokio::select! {
+                        chunk = rx.recv() => match chunk {
+                            Some(chunk) => {
+                                transcript.extend(chunk);
+                                continue;
+                            }
+                            None => format!("Runner closed its output while waiting for {:?}", text),
+                        },
+                        _ = tokio::time::sleep_until(until.into()), if !config.should_abort() => {
+                            format!("Timed out after {:?} waiting for {:?}", timeout, text)
+                        }
+                        _ = aborted(config) => {
+                            monitor.kill_tree_async(&mut child);
+                            let status = monitor
+                                .wait_async(&mut child)
+                                .await
+                                .map_err(|e| format!("Failed waiting on child: {}", e))?;
+                            return Ok(ChildOutcome::Killed(Output {
+                                status,
+                                stdout: transcript,
+                                stderr: killed_output(&mut stderr).await,
+                            }));
+                        }
+                    };
+                    monitor.kill_tree_async(&mut child);
+                    let _ = monitor.wait_async(&mut child).await;
+                    stderr.abort();
+                    return Err(format!(
+                        "{}; output so far:\n{}",
+                        error,
+                        String::from_utf8_lossy(&transcript)
+                    ));
+                }
+            }
+        }
+    }
+
+    // Close stdin so the child sees EOF, then collect whatever it prints on the way out
+    let _ = write_input(stdin, &[], config.pty).await;
+    let (mut output, peak_memory) =
+        match supervise_with_stderr(child, monitor, stderr, &[], config).await? {
+            ChildOutcome::Exited(output, peak_memory) => (output, peak_memory),
+            ChildOutcome::Killed(mut output) => {
+                while let Ok(chunk) = rx.try_recv() {
+                    transcript.extend(chunk);
+                }
+                output.stdout = transcript;
+                return Ok(ChildOutcome::Killed(output));
+            }
+        };
+    while let Some(chunk) = rx.recv().await {
+        transcript.extend(chunk);
+    }
+    output.stdout = transcript.split_off(cursor);
+    Ok(ChildOutcome::Exited(output, peak_memory))
+}
+
+/// Finds the first occurrence of `needle` in `haystack`
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Deserializer};
+use std::path::Path;
+
+/// The `fake_time` header: a fixed time for the child, either a bare timestamp
+/// (`2024-01-01 12:00:00`, UTC, or a Unix epoch like `1700000000`) or a map with more options
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum FakeTimeConfig {
+    At(#[serde(deserialize_with = "deserialize_at")] String),
+    Detailed {
+        #[serde(deserialize_with = "deserialize_at")]
+        at: String,
+        /// Timezone for the child (default: UTC)
+        tz: Option<String>,
+        /// Preload libfaketime so the clock itself is faked, not just the env vars
+        #[serde(default)]
+        preload: bool,
+        /// Path to libfaketime, if it is not in a standard location
+        library: Option<String>,
+    },
+}
+
+/// Reads a timestamp that YAML may give as a number of seconds since the epoch rather than a
+/// string; fractions of a second are dropped
+fn deserialize_at<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum At {
+        Seconds(i64),
+        Fractional(f64),
+        Timestamp(String),
+    }
+    Ok(match At::deserialize(deserializer)? {
+        At::Seconds(epoch) => epoch.to_string(),
+        At::Fractional(epoch) => (epoch.floor() as i64).to_string(),
+        At::Timestamp(at) => at,
+    })
+}
+
+/// Common install locations of libfaketime
+#[cfg(target_os = "macos")]
+const LIBFAKETIME_PATHS: &[&str] = &[
+    "/opt/homebrew/lib/faketime/libfaketime.1.dylib",
+    "/usr/local/lib/faketime/libfaketime.1.dylib",
+];
+#[cfg(not(target_os = "macos"))]
+const LIBFAKETIME_PATHS: &[&str] = &[
+    "/usr/lib/x86_64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib/aarch64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib/faketime/libfaketime.so.1",
+    "/usr/lib64/faketime/libfaketime.so.1",
+    "/usr/local/lib/faketime/libfaketime.so.1",
+];
+
+impl FakeTimeConfig {
+    /// Environment variables that pin the child's notion of the current time
+    pub fn env(&self) -> Result<Vec<(&'static str, String)>, String> {
+        let (at, tz, preload, library) = match self {
+            FakeTimeConfig::At(at) => (at, None, false, None),
+            FakeTimeConfig::Detailed {
+                at,
+                tz,
+                preload,
+                library,
+            } => (at, tz.as_deref(), *preload, library.as_deref()),
+        };
+        let epoch = parse_timestamp(at)
+            .ok_or_else(|| format!("Invalid fake_time {:?} (use YYYY-MM-DD HH:MM:SS)", at))?;
+
+        let mut env = vec![
+            ("FAKETIME", format!("@{}", format_timestamp(epoch))),
+            ("SOURCE_DATE_EPOCH", epoch.to_string()),
+            ("TZ", tz.unwrap_or("UTC").to_string()),
+        ];
+        if preload {
+            let library = library
+                .or_else(|| {
+                    LIBFAKETIME_PATHS
+                        .iter()
+                        .copied()
+                        .find(|p| Path::new(p).is_file())
+                })
+                .ok_or("fake_time preload requested but libfaketime was not found")?;
+            if cfg!(target_os = "macos") {
+                env.push(("DYLD_INSERT_LIBRARIES", library.to_string()));
+                env.push(("DYLD_FORCE_FLAT_NAMESPACE", "1".to_string()));
+            } else if cfg!(unix) {
+                env.push(("LD_PRELOAD", library.to_string()));
+            } else {
+                return Err("fake_time preload is not supported on this platform".to_string());
+            }
+        }
+        Ok(env)
+    }
+}
+
+/// Parses a Unix epoch or a UTC `YYYY-MM-DD[ HH:MM[:SS]]` timestamp into seconds since the epoch
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(epoch) = s.parse() {
+        return Some(epoch);
+    }
+    let (date, time) = s.split_once([' ', 'T']).unwrap_or((s, "00:00:00"));
+    let time = time.trim_end_matches('Z');
+
+    let mut date = date.split('-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.split(':').map(str::parse::<i64>);
+    let hour = time.next()?.ok()?;
+    let minute = time.next()?.ok()?;
+    let second = time.next().unwrap_or(Ok(0)).ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DD HH:MM:SS` (UTC), the format libfaketime expects
+fn format_timestamp(epoch: i64) -> String {
+    let (days, secs) = (epoch.div_euclid(86400), epoch.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
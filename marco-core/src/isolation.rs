@@ -0,0 +1,69 @@
+use std::process::Command;
+
+/// Cuts the child off from the network.
+/// On Linux the child runs in fresh user and network namespaces, which only contain a
+/// downed loopback interface; spawning fails if the kernel does not allow this.
+/// Elsewhere, proxy variables are pointed at a closed port as a best-effort measure.
+pub fn disable_network(cmd: &mut Command) {
+    cmd.env("HTTP_PROXY", DEAD_PROXY)
+        .env("HTTPS_PROXY", DEAD_PROXY)
+        .env("ALL_PROXY", DEAD_PROXY)
+        .env("http_proxy", DEAD_PROXY)
+        .env("https_proxy", DEAD_PROXY)
+        .env("all_proxy", DEAD_PROXY)
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy");
+
+    #[cfg(target_os = "linux")]
+    linux::unshare_network(cmd);
+}
+
+/// Proxy address that refuses every connection (port 9 is the discard service)
+const DEAD_PROXY: &str = "http://127.0.0.1:9";
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CStr;
+    use std::io;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    pub fn unshare_network(cmd: &mut Command) {
+        // Map the current user into the new user namespace so files keep their owners.
+        // The maps are formatted before forking; only raw syscalls run in the child.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let uid_map = format!("{uid} {uid} 1");
+        let gid_map = format!("{gid} {gid} 1");
+
+        // SAFETY: the closure only performs async-signal-safe syscalls
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                write_proc(c"/proc/self/setgroups", b"deny")?;
+                write_proc(c"/proc/self/uid_map", uid_map.as_bytes())?;
+                write_proc(c"/proc/self/gid_map", gid_map.as_bytes())?;
+                Ok(())
+            });
+        }
+    }
+
+    /// Writes `data` to a procfs file without allocating
+    fn write_proc(path: &CStr, data: &[u8]) -> io::Result<()> {
+        // SAFETY: `path` is NUL-terminated and `data` outlives the write
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let result = if libc::write(fd, data.as_ptr().cast(), data.len()) < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            };
+            libc::close(fd);
+            result
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// Bounds how many runners run at once, independently of the number of threads running
+/// tests (`--jobs`)
+#[derive(Debug)]
+pub struct JobSlots {
+    free: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A taken slot, given back when dropped
+pub struct JobSlot<'a>(&'a JobSlots);
+
+impl JobSlots {
+    pub fn new(jobs: usize) -> Self {
+        JobSlots {
+            free: Mutex::new(jobs.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a slot is free and takes it
+    pub fn acquire(&self) -> JobSlot<'_> {
+        let mut free = self.lock();
+        while *free == 0 {
+            free = self
+                .released
+                .wait(free)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *free -= 1;
+        JobSlot(self)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.free.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for JobSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.lock() += 1;
+        self.0.released.notify_one();
+    }
+}
@@ -0,0 +1,36 @@
+//! Collects, runs and checks marco's Markdown tests. The `marco` CLI is a thin layer over this
+//! crate; other tools can embed it to run `.marco.md` files without shelling out:
+//!
+//! ```no_run
+//! use marco_core::{RunOptions, collect_tests, run_suite};
+//!
+//! let tests = collect_tests(&["tests/basic.marco.md".into()])?;
+//! let run = run_suite(&tests, &RunOptions::default(), &());
+//! let failed = run.results.iter().filter(|r| r.failed()).count();
+//! # anyhow::Ok(())
+//! ```
+
+pub mod compare;
+pub mod dialog;
+pub mod faketime;
+pub mod isolation;
+pub mod jobs;
+pub mod mask;
+pub mod memory;
+pub mod normalize;
+pub mod parser;
+#[cfg(unix)]
+pub mod pty;
+pub mod requirements;
+pub mod runner;
+pub mod sandbox;
+pub mod session;
+pub mod types;
+pub mod util;
+pub mod validate;
+pub mod worker;
+
+pub use compare::{Comparator, Comparators, CompareInput, Comparison};
+pub use parser::collect_tests;
+pub use runner::{Observer, RunOptions, SuiteResults, run_suite, run_test_case};
+pub use types::{MarcoTestCase, TestHeader, TestResult};
@@ -0,0 +1,70 @@
+use std::env;
+
+use regex::Regex;
+
+use crate::types::TestResult;
+
+/// Text that replaces every masked secret
+pub const MASK: &str = "***";
+
+/// Secrets to redact from a test's output and reports, built from its `mask` header
+pub struct Mask {
+    patterns: Vec<Regex>,
+}
+
+impl Mask {
+    /// Each entry is either `env:NAME`, masking the value of the environment variable `NAME`,
+    /// or a regex matching the secret itself. Variables the test sets for its runner, in `env`,
+    /// take precedence over marco's own environment; one that is unset or empty is an error,
+    /// as it would silently mask nothing.
+    pub fn new(entries: &[String], env: &[(String, String)]) -> Result<Self, String> {
+        let mut patterns = vec![];
+        for entry in entries {
+            let pattern = match entry.strip_prefix("env:") {
+                Some(name) => {
+                    let set = env.iter().rev().find(|(key, _)| key == name);
+                    match set.map_or_else(|| env::var(name), |(_, value)| Ok(value.clone())) {
+                        Ok(value) if !value.is_empty() => regex::escape(&value),
+                        _ => {
+                            return Err(format!(
+                                "Cannot mask {:?}: the variable is not set or empty",
+                                entry
+                            ));
+                        }
+                    }
+                }
+                None => entry.clone(),
+            };
+            let re = Regex::new(&pattern)
+                .map_err(|e| format!("Invalid mask pattern {:?}: {}", entry, e))?;
+            patterns.push(re);
+        }
+        Ok(Mask { patterns })
+    }
+
+    /// Replaces every secret in `s` with `***`
+    pub fn apply(&self, s: &str) -> String {
+        self.patterns
+            .iter()
+            .fold(s.to_string(), |s, re| re.replace_all(&s, MASK).into_owned())
+    }
+
+    /// Redacts every piece of captured text in a result before it is printed or reported
+    pub fn apply_to_result(&self, result: &mut TestResult) {
+        if self.patterns.is_empty() {
+            return;
+        }
+        result.actual = self.apply(&result.actual);
+        result.expected = self.apply(&result.expected);
+        result.stdout = self.apply(&result.stdout);
+        result.stderr = self.apply(&result.stderr);
+        result.error = result.error.as_deref().map(|e| self.apply(e));
+        result.repro = result.repro.as_deref().map(|r| self.apply(r));
+        for warning in &mut result.warnings {
+            *warning = self.apply(warning);
+        }
+        for attempt in &mut result.attempts {
+            attempt.error = attempt.error.as_deref().map(|e| self.apply(e));
+        }
+    }
+}
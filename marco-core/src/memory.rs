@@ -0,0 +1,397 @@
+use std::io;
+use std::process::{Child, ExitStatus};
+
+/// Waits on a child process while tracking its peak memory usage.
+/// On Linux the high-water RSS of the child and its descendants is sampled while it runs, as
+/// the `ru_maxrss` of `wait4` also counts the memory marco's forked copy held before `exec`;
+/// on other Unixes the child is reaped with `wait4` to read its `ru_maxrss`;
+/// on Windows it is placed in a Job Object whose accounting records the peak.
+pub struct ChildMonitor {
+    peak: Option<u64>,
+    /// Registered with `kill_running` until the child is reaped or no longer monitored
+    #[cfg(unix)]
+    pid: u32,
+    #[cfg(target_os = "linux")]
+    sampler: linux::Sampler,
+    /// `wait4` of a child spawned by tokio, once `wait_async` started it
+    #[cfg(all(unix, not(target_os = "linux")))]
+    reaper: Option<tokio::task::JoinHandle<io::Result<Option<(ExitStatus, u64)>>>>,
+    #[cfg(windows)]
+    job: Option<windows::Job>,
+}
+
+impl ChildMonitor {
+    /// Starts monitoring a freshly spawned child
+    pub fn attach(child: &Child) -> Self {
+        #[cfg(windows)]
+        let handle = std::os::windows::io::AsRawHandle::as_raw_handle(child);
+        ChildMonitor::start(
+            child.id(),
+            #[cfg(windows)]
+            handle,
+        )
+    }
+
+    /// Starts monitoring a child freshly spawned by tokio
+    pub fn attach_async(child: &tokio::process::Child) -> Self {
+        #[cfg(windows)]
+        let handle = child.raw_handle().expect("a running child has a handle");
+        ChildMonitor::start(
+            child.id().expect("a running child has a pid"),
+            #[cfg(windows)]
+            handle,
+        )
+    }
+
+    #[allow(unused_variables)]
+    fn start(pid: u32, #[cfg(windows)] handle: std::os::windows::io::RawHandle) -> Self {
+        #[cfg(unix)]
+        unix::running().push(pid);
+        ChildMonitor {
+            peak: None,
+            #[cfg(unix)]
+            pid,
+            #[cfg(target_os = "linux")]
+            sampler: linux::Sampler::start(pid),
+            #[cfg(all(unix, not(target_os = "linux")))]
+            reaper: None,
+            #[cfg(windows)]
+            job: windows::Job::assign(handle),
+        }
+    }
+
+    /// Peak memory of the child in bytes, once it has exited and if the platform reports it
+    pub fn peak_memory(&self) -> Option<u64> {
+        self.peak
+    }
+
+    /// Returns the exit status if the child has exited, without blocking
+    #[allow(unused_variables)]
+    pub fn try_wait(&mut self, child: &mut Child) -> io::Result<Option<ExitStatus>> {
+        #[cfg(unix)]
+        {
+            Ok(unix::wait4(self.pid, true)?.map(|(status, peak)| {
+                self.record_exit(Some(peak));
+                status
+            }))
+        }
+        #[cfg(not(unix))]
+        {
+            let status = child.try_wait()?;
+            if status.is_some() {
+                self.record_exit(None);
+            }
+            Ok(status)
+        }
+    }
+
+    /// Waits until a child spawned by tokio exits. Cancelling it and waiting again is fine.
+    #[allow(unused_variables)]
+    pub async fn wait_async(
+        &mut self,
+        child: &mut tokio::process::Child,
+    ) -> io::Result<ExitStatus> {
+        // Elsewhere the peak is known without reaping the child with `wait4`, so tokio does
+        #[cfg(all(unix, not(target_os = "linux")))]
+        {
+            let pid = self.pid;
+            let reaper = self.reaper.get_or_insert_with(|| {
+                tokio::task::spawn_blocking(move || unix::wait4(pid, false))
+            });
+            let (status, peak) = reaper
+                .await
+                .map_err(io::Error::other)??
+                .expect("blocking wait4 returned early");
+            self.record_exit(Some(peak));
+            Ok(status)
+        }
+        #[cfg(not(all(unix, not(target_os = "linux"))))]
+        {
+            let status = child.wait().await?;
+            self.record_exit(None);
+            Ok(status)
+        }
+    }
+
+    /// Kills the child along with every process it started: its process group on Unix,
+    /// its Job Object on Windows. Falls back to killing just the child.
+    pub fn kill_tree(&self, child: &mut Child) {
+        self.kill_descendants();
+        let _ = child.kill();
+    }
+
+    /// Like `kill_tree`, for a child spawned by tokio
+    pub fn kill_tree_async(&self, child: &mut tokio::process::Child) {
+        self.kill_descendants();
+        let _ = child.start_kill();
+    }
+
+    fn kill_descendants(&self) {
+        #[cfg(unix)]
+        {
+            // SAFETY: the child leads its own process group (see `spawn_child`), so the
+            // negative pid only reaches it and its descendants
+            unsafe {
+                libc::kill(-(self.pid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            job.terminate();
+        }
+    }
+
+    /// Records the peak once the child was reaped, given its `ru_maxrss` in bytes where
+    /// `wait4` reaped it
+    #[allow(unused_variables)]
+    fn record_exit(&mut self, maxrss: Option<u64>) {
+        #[cfg(unix)]
+        unix::unregister(self.pid);
+        #[cfg(target_os = "linux")]
+        {
+            self.peak = Some(self.sampler.finish());
+        }
+        #[cfg(all(unix, not(target_os = "linux")))]
+        {
+            self.peak = maxrss;
+        }
+        #[cfg(windows)]
+        {
+            self.peak = self.job.as_ref().and_then(windows::Job::peak_memory);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ChildMonitor {
+    fn drop(&mut self) {
+        unix::unregister(self.pid);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    /// How often the process tree's memory is sampled, more often at first to catch short-lived
+    /// runners
+    const FIRST_INTERVAL: Duration = Duration::from_millis(1);
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Samples the summed `VmHWM` of a process and its descendants on a thread of its own
+    pub struct Sampler {
+        peak: Arc<AtomicU64>,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Sampler {
+        /// Takes a first sample right away, then keeps sampling until `finish`
+        pub fn start(pid: u32) -> Self {
+            let peak = Arc::new(AtomicU64::new(tree_hwm(pid)));
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = {
+                let peak = Arc::clone(&peak);
+                let stop = Arc::clone(&stop);
+                thread::spawn(move || {
+                    let mut interval = FIRST_INTERVAL;
+                    while !stop.load(Ordering::Relaxed) {
+                        thread::park_timeout(interval);
+                        peak.fetch_max(tree_hwm(pid), Ordering::Relaxed);
+                        interval = (interval * 2).min(INTERVAL);
+                    }
+                })
+            };
+            Sampler {
+                peak,
+                stop,
+                thread: Some(thread),
+            }
+        }
+
+        /// Stops sampling and returns the highest sample in bytes. Growth after the last sample
+        /// is missed, so a child that exited before it could be sampled counts as using none.
+        pub fn finish(&mut self) -> u64 {
+            self.stop();
+            self.peak.load(Ordering::Relaxed)
+        }
+
+        fn stop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                thread.thread().unpark();
+                let _ = thread.join();
+            }
+        }
+    }
+
+    impl Drop for Sampler {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    /// Summed high-water RSS in bytes of a process and everything it started that is still
+    /// running; processes that already exited contribute nothing
+    fn tree_hwm(pid: u32) -> u64 {
+        let own = fs::read_to_string(format!("/proc/{}/status", pid))
+            .ok()
+            .and_then(|status| {
+                let hwm = status
+                    .lines()
+                    .find_map(|line| line.strip_prefix("VmHWM:"))?;
+                let kb: u64 = hwm.trim().strip_suffix("kB")?.trim().parse().ok()?;
+                Some(kb * 1024)
+            })
+            .unwrap_or(0);
+        let children: u64 = fs::read_dir(format!("/proc/{}/task", pid))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|task| fs::read_to_string(task.path().join("children")).ok())
+            .flat_map(|children| {
+                children
+                    .split_whitespace()
+                    .filter_map(|child| child.parse().ok())
+                    .collect::<Vec<u32>>()
+            })
+            .map(tree_hwm)
+            .sum();
+        own + children
+    }
+}
+
+/// Kills the process trees of all children that are still running, e.g. when marco is
+/// interrupted: they lead process groups of their own, so Ctrl-C in a terminal misses them
+#[cfg(unix)]
+pub fn kill_running() {
+    for pid in unix::running().drain(..) {
+        // SAFETY: every registered child leads its own process group (see `spawn_child`)
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Pids of the children that were spawned and not reaped yet
+    static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    pub fn running() -> MutexGuard<'static, Vec<u32>> {
+        RUNNING
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn unregister(pid: u32) {
+        running().retain(|&running| running != pid);
+    }
+
+    /// Reaps the child with `wait4`, returning its exit status and peak RSS in bytes.
+    /// Returns `None` if `nohang` is set and the child is still running.
+    pub fn wait4(pid: u32, nohang: bool) -> io::Result<Option<(ExitStatus, u64)>> {
+        let pid = pid as libc::pid_t;
+        let options = if nohang { libc::WNOHANG } else { 0 };
+        let mut status = 0;
+        // SAFETY: rusage is plain old data and wait4 only writes into the provided pointers
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        loop {
+            let ret = unsafe { libc::wait4(pid, &mut status, options, &mut usage) };
+            match ret {
+                0 => return Ok(None),
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                _ => break,
+            }
+        }
+        // ru_maxrss is reported in bytes on macOS and in kilobytes elsewhere
+        let maxrss = usage.ru_maxrss.max(0) as u64;
+        let peak = if cfg!(target_os = "macos") {
+            maxrss
+        } else {
+            maxrss * 1024
+        };
+        Ok(Some((ExitStatus::from_raw(status), peak)))
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::os::windows::io::RawHandle;
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JobObjectExtendedLimitInformation, QueryInformationJobObject, TerminateJobObject,
+    };
+
+    /// An anonymous Job Object containing the child and any processes it spawns
+    pub struct Job(HANDLE);
+
+    impl Job {
+        /// Creates a job and assigns the child with this handle to it, or `None` if either step
+        /// fails
+        pub fn assign(child: RawHandle) -> Option<Self> {
+            // SAFETY: both handles are valid for the duration of the calls
+            unsafe {
+                let handle = CreateJobObjectW(ptr::null(), ptr::null());
+                if handle.is_null() {
+                    return None;
+                }
+                let job = Job(handle);
+                if AssignProcessToJobObject(job.0, child as HANDLE) == 0 {
+                    return None;
+                }
+                Some(job)
+            }
+        }
+
+        /// Kills every process in the job
+        pub fn terminate(&self) {
+            // SAFETY: the handle stays valid until the job is dropped
+            unsafe {
+                TerminateJobObject(self.0, 1);
+            }
+        }
+
+        /// Peak memory committed by any process in the job, in bytes
+        pub fn peak_memory(&self) -> Option<u64> {
+            // SAFETY: the info struct is plain old data and sized correctly for the query
+            unsafe {
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                let ok = QueryInformationJobObject(
+                    self.0,
+                    JobObjectExtendedLimitInformation,
+                    &mut info as *mut _ as *mut _,
+                    std::mem::size_of_val(&info) as u32,
+                    ptr::null_mut(),
+                );
+                (ok != 0).then_some(info.PeakProcessMemoryUsed as u64)
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // SAFETY: the handle was returned by CreateJobObjectW and is closed only once
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use regex::Regex;
+use serde::Deserialize;
+
+/// One entry of the `normalize` header
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizeRule {
+    /// Regex to look for in the output
+    pub pattern: String,
+    /// Text every match is replaced with; `$1` or `${name}` insert capture groups
+    #[serde(default)]
+    pub replace: String,
+}
+
+/// Rewrites output before it is compared, e.g. to scrub hostnames, temp paths and UUIDs,
+/// built from a test's `normalize` header
+pub struct Normalizer {
+    rules: Vec<(Regex, String)>,
+}
+
+impl Normalizer {
+    pub fn new(rules: &[NormalizeRule]) -> Result<Self, String> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Regex::new(&rule.pattern)
+                    .map(|re| (re, rule.replace.clone()))
+                    .map_err(|e| format!("Invalid normalize pattern {:?}: {}", rule.pattern, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Normalizer { rules })
+    }
+
+    /// Applies every rule to `s` in order
+    pub fn apply(&self, s: &str) -> String {
+        self.rules.iter().fold(s.to_string(), |s, (re, replace)| {
+            re.replace_all(&s, replace.as_str()).into_owned()
+        })
+    }
+}
+
+/// Case and whitespace differences to ignore when comparing output, set in a test's header
+/// or for every test in the config files
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct TextOptions {
+    pub ignore_case: Option<bool>,
+    /// Ignore spaces and tabs at the end of lines
+    pub ignore_trailing_whitespace: Option<bool>,
+    /// Treat every run of spaces and tabs within a line as one space
+    pub squash_whitespace: Option<bool>,
+    /// Drop lines that are empty or only hold whitespace
+    pub ignore_blank_lines: Option<bool>,
+}
+
+impl TextOptions {
+    /// Takes every option from `self` and falls back to `lower` for the ones it does not set
+    pub fn or(self, lower: TextOptions) -> TextOptions {
+        TextOptions {
+            ignore_case: self.ignore_case.or(lower.ignore_case),
+            ignore_trailing_whitespace: self
+                .ignore_trailing_whitespace
+                .or(lower.ignore_trailing_whitespace),
+            squash_whitespace: self.squash_whitespace.or(lower.squash_whitespace),
+            ignore_blank_lines: self.ignore_blank_lines.or(lower.ignore_blank_lines),
+        }
+    }
+
+    /// Applies the enabled options to `s`; without any, `s` is returned as it is
+    pub fn apply(&self, s: &str) -> String {
+        let enabled = |option: Option<bool>| option.unwrap_or(false);
+        if !enabled(self.ignore_case)
+            && !enabled(self.ignore_trailing_whitespace)
+            && !enabled(self.squash_whitespace)
+            && !enabled(self.ignore_blank_lines)
+        {
+            return s.to_string();
+        }
+        let mut lines = vec![];
+        for line in s.lines() {
+            if enabled(self.ignore_blank_lines) && line.trim().is_empty() {
+                continue;
+            }
+            let mut line = line.to_string();
+            if enabled(self.squash_whitespace) {
+                let mut squashed = String::with_capacity(line.len());
+                for c in line.chars() {
+                    let blank = c == ' ' || c == '\t';
+                    if !(blank && squashed.ends_with(' ')) {
+                        squashed.push(if blank { ' ' } else { c });
+                    }
+                }
+                line = squashed;
+            }
+            if enabled(self.ignore_trailing_whitespace) {
+                line.truncate(line.trim_end().len());
+            }
+            if enabled(self.ignore_case) {
+                line = line.to_lowercase();
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,776 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, anyhow};
+use dom_query::{Document, Selection};
+use markdown::mdast::Node;
+use markdown::unist::Position;
+use markdown::{ParseOptions, to_html, to_mdast};
+use serde_yml;
+
+use crate::dialog::{DEFAULT_EXPECT_TIMEOUT, DialogStep, parse_dialog};
+use crate::types::{MarcoTestCase, MatrixValue, RunnerConfig, TestHeader};
+use crate::util::{decode_base64, decode_hex, is_relative_inside};
+
+/// Collects all test cases from the set of markdown test files, file by file and lowest `order`
+/// first within each file
+pub fn collect_tests(files: &[PathBuf]) -> Result<Vec<MarcoTestCase>> {
+    let mut all = vec![];
+    for file in files {
+        let src =
+            fs::read_to_string(file).with_context(|| format!("Failed to read file {:?}", file))?;
+        let mut tests = parse_test_markdown_html(file.clone(), &src)?;
+        // Stable, so tests with the same order keep their in-file order
+        tests.sort_by_key(|t| t.header.order);
+        all.append(&mut tests);
+    }
+    Ok(all)
+}
+
+/// Parses a markdown file as HTML and extracts a list of test cases
+pub fn parse_test_markdown_html(file: PathBuf, src: &str) -> Result<Vec<MarcoTestCase>> {
+    let mut result: Vec<MarcoTestCase> = Vec::new();
+    let html = to_html(src);
+    let document = Document::from(html.clone());
+    let frontmatter = document.try_select("h2:first-of-type");
+
+    if frontmatter.is_none() {
+        eprintln!("Warning: no frontmatter found in file {:?}", file);
+
+        return Ok(vec![]);
+    }
+
+    let frontmatter = frontmatter.unwrap().text();
+    let header: TestHeader = serde_yml::from_str(&frontmatter)
+        .map_err(|e| anyhow!("Failed to parse frontmatter as header: {}", e))?;
+
+    // Collect all pre blocks' text into a Vec
+    let pre_blocks: Vec<_> = document.select("pre").iter().collect();
+    let file_newline = if src.contains("\r\n") { "\r\n" } else { "\n" };
+    let block_text = |el: &Selection| header.line_endings.apply(&el.text(), file_newline);
+    // Only used for line numbers, so a file mdast cannot parse just gets none
+    let positions = code_block_positions(src).unwrap_or_default();
+    // Info strings after the language are not part of the HTML, so `path=` comes from mdast
+    let paths = code_block_paths(src).unwrap_or_default();
+    // "Files" blocks (```` ```json path=config.json ````) belong to the following test, unless
+    // they are under an "Expected Files" heading
+    let mut files = vec![];
+    // So does an "Args" section's block
+    let mut args = vec![];
+
+    // Pair each input <pre> block with the expected output block(s) that follow it
+    let mut i = 0;
+    while i < pre_blocks.len() {
+        if is_args_block(&pre_blocks[i]) {
+            args.extend(pre_blocks[i].text().lines().map(str::to_string));
+            i += 1;
+            continue;
+        }
+        if let Some(Some(path)) = paths.get(i) {
+            if !is_relative_inside(path) {
+                return Err(anyhow!(
+                    "File block path {:?} in file {:?} must be relative and stay inside the working directory",
+                    path,
+                    file
+                ));
+            }
+            if !is_expected_files_block(&pre_blocks[i]) {
+                files.push((path.clone(), pre_blocks[i].text().to_string()));
+                i += 1;
+                continue;
+            }
+        }
+        // "Expected Files" blocks (```` ```file=out.txt ```` or ```` ```json path=out.json ````
+        // under an "Expected Files" heading) belong to the preceding test
+        if let Some(path) =
+            block_file_path(&pre_blocks[i]).or_else(|| paths.get(i).cloned().flatten())
+        {
+            if !is_relative_inside(&path) {
+                return Err(anyhow!(
+                    "Expected file path {:?} in file {:?} must be relative and stay inside the working directory",
+                    path,
+                    file
+                ));
+            }
+            let Some(test) = result.last_mut() else {
+                return Err(anyhow!(
+                    "Expected file block {:?} appears before any test in file {:?}",
+                    path,
+                    file
+                ));
+            };
+            let content = pre_blocks[i].text().to_string();
+            test.expected_files.push((path, content));
+            i += 1;
+            continue;
+        }
+        // So do "Expected Stderr" blocks; a platform-specific one wins over a generic one
+        if is_stderr_block(&pre_blocks[i]) {
+            let Some(test) = result.last_mut() else {
+                return Err(anyhow!(
+                    "Expected Stderr block appears before any test in file {:?}",
+                    file
+                ));
+            };
+            let platform = block_platform(&pre_blocks[i]);
+            if platform.as_deref() == Some(CURRENT_PLATFORM)
+                || (platform.is_none() && test.expected_stderr.is_none())
+            {
+                test.expected_stderr = Some(block_text(&pre_blocks[i]));
+            }
+            i += 1;
+            continue;
+        }
+
+        let Some(first_expected) = pre_blocks.get(i + 1) else {
+            return Err(anyhow!(
+                "Unmatched input/expected output pair in file {:?} (Input block at line {})",
+                file,
+                positions.get(i).map_or(0, |p| p.start.line)
+            ));
+        };
+        let input = &pre_blocks[i];
+        let input_line = positions.get(i).map_or(0, |p| p.start.line);
+
+        // Platform-specific variants ("Expected Output (windows)") directly follow the first block
+        let mut variants = vec![(block_platform(first_expected), i + 1)];
+        i += 2;
+        while let Some(block) = pre_blocks.get(i)
+            && !is_stderr_block(block)
+            && let Some(platform) = block_platform(block)
+        {
+            variants.push((Some(platform), i));
+            i += 1;
+        }
+        let expected = variants
+            .iter()
+            .find(|(platform, _)| platform.as_deref() == Some(CURRENT_PLATFORM))
+            .or_else(|| variants.iter().find(|(platform, _)| platform.is_none()));
+
+        let mut header = header.clone();
+
+        if let Some(title) = get_el_title(input.clone()) {
+            header.name = format!("{}: {}", header.name, title);
+        }
+
+        let Some(&(_, expected_block)) = expected else {
+            return Err(anyhow!(
+                "Test {:?} in file {:?} has no Expected Output block for {}",
+                header.name,
+                file,
+                CURRENT_PLATFORM
+            ));
+        };
+
+        let input_data = block_text(input);
+        let dialog = if input.select("code").has_class("language-dialog") {
+            let steps = parse_dialog(&input.text()).map_err(|e| {
+                anyhow!(
+                    "Invalid dialog for test {:?} in file {:?}: {}",
+                    header.name,
+                    file,
+                    e
+                )
+            })?;
+            Some(steps)
+        } else {
+            None
+        };
+        let raw_input = decode_block(input).map_err(|e| {
+            anyhow!(
+                "Invalid encoded Input block for test {:?} in file {:?}: {}",
+                header.name,
+                file,
+                e
+            )
+        })?;
+        let expected_output = block_text(&pre_blocks[expected_block]);
+        let expected_sha256 = parse_sha256_block(&pre_blocks[expected_block]).map_err(|e| {
+            anyhow!(
+                "Invalid sha256 Expected Output block for test {:?} in file {:?}: {}",
+                header.name,
+                file,
+                e
+            )
+        })?;
+        let expected_bytes = decode_block(&pre_blocks[expected_block]).map_err(|e| {
+            anyhow!(
+                "Invalid encoded Expected Output block for test {:?} in file {:?}: {}",
+                header.name,
+                file,
+                e
+            )
+        })?;
+        let test_case = MarcoTestCase {
+            header: header.clone(),
+            file: file.clone(),
+            input_data,
+            dialog,
+            input_lang: block_language(input),
+            raw_input,
+            expected_output,
+            expected_bytes,
+            expected_sha256,
+            expected_regex: block_language(&pre_blocks[expected_block]).as_deref() == Some("regex"),
+            expected_yaml: matches!(
+                block_language(&pre_blocks[expected_block]).as_deref(),
+                Some("yaml" | "yml")
+            ),
+            expected_stderr: None,
+            block_start_line: input_line,
+            skip: None,
+            files: std::mem::take(&mut files),
+            args: std::mem::take(&mut args),
+            expected_files: vec![],
+            expected_block: Some(expected_block),
+        };
+        // In an interactive file, further pairs under a test's heading continue its dialog
+        if header.interactive
+            && let Some(previous) =
+                result.pop_if(|previous| previous.header.name == test_case.header.name)
+        {
+            result.push(continue_dialog(previous, test_case));
+            continue;
+        }
+        result.push(test_case);
+    }
+
+    if let Some((path, _)) = files.first() {
+        return Err(anyhow!(
+            "File block {:?} in file {:?} is not followed by a test",
+            path,
+            file
+        ));
+    }
+    if !args.is_empty() {
+        return Err(anyhow!(
+            "Args block in file {:?} is not followed by a test",
+            file
+        ));
+    }
+
+    if header.interpolate || !header.mask.is_empty() {
+        // The block holds variables, or the output it would be rewritten with is masked, so it
+        // cannot be rewritten with what the runner printed
+        for test in &mut result {
+            test.expected_block = None;
+        }
+    }
+    if let Some(matrix) = &header.matrix {
+        for (key, values) in matrix {
+            if values.is_empty() {
+                return Err(anyhow!(
+                    "Matrix key {:?} in file {:?} has no values, which would leave no tests",
+                    key,
+                    file
+                ));
+            }
+            if RESERVED_MATRIX_KEYS.contains(&key.as_str()) {
+                return Err(anyhow!(
+                    "Matrix key {:?} in file {:?} is reserved for marco's own `{{{}}}`",
+                    key,
+                    file,
+                    key
+                ));
+            }
+        }
+        result = expand_matrix(result, matrix);
+    }
+    if let Some(runners) = &header.runners {
+        if header.runner.is_some() {
+            return Err(anyhow!(
+                "File {:?} sets both `runner` and `runners` in its header",
+                file
+            ));
+        }
+        result = expand_runners(result, runners);
+    }
+
+    Ok(result)
+}
+
+/// Placeholders the runner fills in itself, which a matrix key would shadow
+const RESERVED_MATRIX_KEYS: &[&str] = &["seed"];
+
+/// Replaces `{key}` in `s` with `value`, leaving the runner's own `{{key}}` variables alone
+fn substitute_key(s: &str, key: &str, value: &str) -> String {
+    let placeholder = format!("{{{}}}", key);
+    let mut replaced = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find(&placeholder) {
+        let end = start + placeholder.len();
+        let doubled = rest[..start].ends_with('{') && rest[end..].starts_with('}');
+        replaced.push_str(&rest[..start]);
+        replaced.push_str(if doubled { &placeholder } else { value });
+        rest = &rest[end..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// Turns each test into one case per combination of matrix values, e.g. "Echo [mode=fast]"
+fn expand_matrix(
+    tests: Vec<MarcoTestCase>,
+    matrix: &BTreeMap<String, Vec<MatrixValue>>,
+) -> Vec<MarcoTestCase> {
+    // Cartesian product of all keys' values
+    let mut combinations: Vec<Vec<(&str, String)>> = vec![vec![]];
+    for (key, values) in matrix {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((key.as_str(), value.to_string()));
+                    combination
+                })
+            })
+            .collect();
+    }
+
+    let mut expanded = vec![];
+    for test in tests {
+        for combination in &combinations {
+            let substitute = |s: &str| {
+                combination.iter().fold(s.to_string(), |s, (key, value)| {
+                    substitute_key(&s, key, value)
+                })
+            };
+            let label = combination
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let mut test = test.clone();
+            test.header.name = format!("{} [{}]", test.header.name, label);
+            test.header.runner = test.header.runner.map(|r| r.map(&substitute));
+            test.header.runners = test.header.runners.map(|runners| {
+                runners
+                    .into_iter()
+                    .map(|(variant, r)| (variant, r.map(&substitute)))
+                    .collect()
+            });
+            test.header.matrix = None;
+            // The block holds placeholders, so it cannot be rewritten with one combination's output
+            test.expected_block = None;
+            test.input_data = substitute(&test.input_data);
+            test.expected_output = substitute(&test.expected_output);
+            test.expected_stderr = test.expected_stderr.as_deref().map(substitute);
+            test.dialog = test.dialog.map(|steps| {
+                steps
+                    .into_iter()
+                    .map(|step| match step {
+                        DialogStep::Send(line) => DialogStep::Send(substitute(&line)),
+                        DialogStep::Expect { text, timeout } => DialogStep::Expect {
+                            text: substitute(&text),
+                            timeout,
+                        },
+                    })
+                    .collect()
+            });
+            expanded.push(test);
+        }
+    }
+    expanded
+}
+
+/// Turns each test into one case per named runner variant, e.g. "Echo [release]"
+fn expand_runners(
+    tests: Vec<MarcoTestCase>,
+    runners: &BTreeMap<String, RunnerConfig>,
+) -> Vec<MarcoTestCase> {
+    tests
+        .into_iter()
+        .flat_map(|test| {
+            runners.iter().map(move |(variant, runner)| {
+                let mut test = test.clone();
+                test.header.name = format!("{} [{}]", test.header.name, variant);
+                test.header.runner = Some(runner.clone());
+                test.header.runners = None;
+                test
+            })
+        })
+        .collect()
+}
+
+/// Platform name used to pick between platform-specific Expected Output blocks
+#[cfg(windows)]
+const CURRENT_PLATFORM: &str = "windows";
+#[cfg(not(windows))]
+const CURRENT_PLATFORM: &str = "unix";
+
+/// Replaces the content of fenced code blocks, given as (index among all code blocks, new content).
+/// The fences, their info strings and the rest of the file are kept as they are.
+pub fn replace_code_blocks(src: &str, updates: &BTreeMap<usize, String>) -> Result<String> {
+    let blocks = code_block_positions(src)?;
+
+    let newline = if src.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out = src.to_string();
+    // Back to front, so earlier offsets stay valid
+    for (&index, content) in updates.iter().rev() {
+        let position = blocks
+            .get(index)
+            .ok_or_else(|| anyhow!("Code block #{} not found", index))?;
+        let (start, end) = (position.start.offset, position.end.offset);
+        let block = &src[start..end];
+        let opening = block.lines().next().unwrap_or_default().trim_start();
+        // Content lines line up with the fence, e.g. inside a list item
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let indent: String = src[line_start..start].chars().map(|_| ' ').collect();
+        let fence_char = opening.chars().next().unwrap_or(' ');
+        if fence_char != '`' && fence_char != '~' {
+            return Err(anyhow!(
+                "Code block #{} is not fenced and cannot be rewritten",
+                index
+            ));
+        }
+        let fence_len = opening.chars().take_while(|&c| c == fence_char).count();
+        let info = &opening[fence_len..];
+        // Lengthen the fence if the new content contains a line that would close it
+        let longest = content
+            .lines()
+            .map(|line| {
+                line.trim_start()
+                    .chars()
+                    .take_while(|&c| c == fence_char)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0);
+        let fence = fence_char.to_string().repeat(fence_len.max(longest + 1));
+
+        let mut replacement = format!("{}{}{}", fence, info, newline);
+        for line in content.lines() {
+            replacement.push_str(&indent);
+            replacement.push_str(line);
+            replacement.push_str(newline);
+        }
+        replacement.push_str(&indent);
+        replacement.push_str(&fence);
+        out.replace_range(start..end, &replacement);
+    }
+    Ok(out)
+}
+
+/// Source positions of every code block in document order, matching the file's `<pre>` blocks
+pub fn code_block_positions(src: &str) -> Result<Vec<Position>> {
+    let tree = to_mdast(src, &ParseOptions::default())
+        .map_err(|e| anyhow!("Failed to parse markdown: {}", e))?;
+    let mut blocks = vec![];
+    collect_code_positions(&tree, &mut blocks);
+    Ok(blocks)
+}
+
+fn collect_code_positions(node: &Node, blocks: &mut Vec<Position>) {
+    if let Node::Code(code) = node
+        && let Some(position) = &code.position
+    {
+        blocks.push(position.clone());
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_code_positions(child, blocks);
+    }
+}
+
+/// Merges the Input/Expected Output pair of `next` into the dialog of the `interactive` test
+/// `previous`: its input is sent once the runner has printed the previous Expected Output,
+/// and its Expected Output becomes the one the rest of the output is compared with
+fn continue_dialog(previous: MarcoTestCase, next: MarcoTestCase) -> MarcoTestCase {
+    let sent = |test: &MarcoTestCase| {
+        test.dialog.clone().unwrap_or_else(|| {
+            test.input_data
+                .lines()
+                .map(|line| DialogStep::Send(line.to_string()))
+                .collect()
+        })
+    };
+    let mut steps = sent(&previous);
+    // A `timeout:` line of an earlier dialog block still applies
+    let timeout = steps
+        .iter()
+        .rev()
+        .find_map(|step| match step {
+            DialogStep::Expect { timeout, .. } => Some(*timeout),
+            DialogStep::Send(_) => None,
+        })
+        .unwrap_or(DEFAULT_EXPECT_TIMEOUT);
+    steps.push(DialogStep::Expect {
+        text: previous.expected_output.trim().to_string(),
+        timeout,
+    });
+    steps.extend(sent(&next));
+    MarcoTestCase {
+        input_data: previous.input_data + &next.input_data,
+        dialog: Some(steps),
+        raw_input: None,
+        block_start_line: previous.block_start_line,
+        files: previous.files,
+        args: previous.args,
+        ..next
+    }
+}
+
+/// Gets the `path=` of each code block's info string (```` ```text path=config.json ````),
+/// in the order of the blocks
+pub fn code_block_paths(src: &str) -> Result<Vec<Option<String>>> {
+    let tree = to_mdast(src, &ParseOptions::default())
+        .map_err(|e| anyhow!("Failed to parse markdown: {}", e))?;
+    let mut paths = vec![];
+    collect_code_paths(&tree, &mut paths);
+    Ok(paths)
+}
+
+fn collect_code_paths(node: &Node, paths: &mut Vec<Option<String>>) {
+    if let Node::Code(code) = node {
+        let path = code.meta.as_deref().and_then(|meta| {
+            meta.split_whitespace()
+                .find_map(|word| word.strip_prefix("path="))
+                .filter(|path| !path.is_empty())
+                .map(str::to_string)
+        });
+        paths.push(path);
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_code_paths(child, paths);
+    }
+}
+
+/// Gets the platform a `<pre>` block is restricted to, either from its fence tag
+/// (```` ```windows ````) or from a preceding label like "Expected Output (unix):"
+pub fn block_platform(el: &Selection) -> Option<String> {
+    for platform in ["windows", "unix"] {
+        if el
+            .select("code")
+            .has_class(&format!("language-{}", platform))
+        {
+            return Some(platform.to_string());
+        }
+    }
+
+    let text = block_label(el)?;
+    ["windows", "unix"]
+        .into_iter()
+        .find(|platform| text.ends_with(&format!("({})", platform)))
+        .map(str::to_string)
+}
+
+/// Whether a `<pre>` block is labelled "Expected Stderr", by a heading or a paragraph like
+/// "Expected Stderr:", and so holds the stderr the preceding test must print
+pub fn is_stderr_block(el: &Selection) -> bool {
+    block_label(el).is_some_and(|label| {
+        label
+            .get(.."expected stderr".len())
+            .is_some_and(|start| start.eq_ignore_ascii_case("expected stderr"))
+    })
+}
+
+/// The text of the paragraph or heading directly before a `<pre>` block, without a trailing colon
+fn block_label(el: &Selection) -> Option<String> {
+    // Skip whitespace text nodes between the label and the block
+    let mut label = el.prev_sibling();
+    while !label.is_empty() && !label.is("*") {
+        label = label.prev_sibling();
+    }
+    if !label.is("p, h1, h2, h3, h4, h5, h6") {
+        return None;
+    }
+    let text = label.text();
+    Some(text.trim().trim_end_matches(':').trim_end().to_string())
+}
+
+/// Gets the language of a block from its fence tag, using the extension for file names
+/// (```` ```python ```` gives `python`, ```` ```main.py ```` gives `py`)
+pub fn block_language(el: &Selection) -> Option<String> {
+    let class = el.select("code").attr("class")?;
+    let lang = class
+        .split_whitespace()
+        .find_map(|c| c.strip_prefix("language-"))?;
+    let lang = lang.rsplit_once('.').map_or(lang, |(_, ext)| ext);
+    (!lang.is_empty()).then(|| lang.to_ascii_lowercase())
+}
+
+/// Decodes an Input or Expected Output block tagged ```` ```hex ```` or ```` ```base64 ```` into
+/// raw bytes. Returns `None` for untagged blocks, which hold text.
+pub fn decode_block(el: &Selection) -> Result<Option<Vec<u8>>> {
+    let code = el.select("code");
+    let text = el.text();
+    if code.has_class("language-hex") {
+        decode_hex(&text).map(Some)
+    } else if code.has_class("language-base64") {
+        decode_base64(&text).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads the digest of an Expected Output block tagged ```` ```sha256 ````, in lowercase.
+/// Returns `None` for blocks with other tags.
+pub fn parse_sha256_block(el: &Selection) -> Result<Option<String>> {
+    if !el.select("code").has_class("language-sha256") {
+        return Ok(None);
+    }
+    let digest = el.text().trim().to_ascii_lowercase();
+    if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(anyhow!("expected 64 hex digits, got {:?}", digest));
+    }
+    Ok(Some(digest))
+}
+
+/// Gets the path of an expected file block from its fence tag (```` ```file=out/result.txt ````)
+pub fn block_file_path(el: &Selection) -> Option<String> {
+    let class = el.select("code").attr("class")?;
+    class
+        .split_whitespace()
+        .find_map(|c| c.strip_prefix("language-file="))
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+}
+
+/// Whether a `<pre>` block is in an "Expected Files" section, i.e. its nearest preceding
+/// heading is "Expected Files"
+fn is_expected_files_block(el: &Selection) -> bool {
+    let mut current = el.prev_sibling();
+    while !current.is_empty() {
+        if current.is("h1, h2, h3, h4, h5, h6") {
+            return current.text().trim().eq_ignore_ascii_case("expected files");
+        }
+        current = current.prev_sibling();
+    }
+    false
+}
+
+/// Whether a `<pre>` block is the first one after an "Args" heading; the Input block that
+/// follows it is in the same section
+fn is_args_block(el: &Selection) -> bool {
+    let mut current = el.prev_sibling();
+    while !current.is_empty() {
+        if current.is("pre") {
+            return false;
+        }
+        if current.is("h1, h2, h3, h4, h5, h6") {
+            return current.text().trim().eq_ignore_ascii_case("args");
+        }
+        current = current.prev_sibling();
+    }
+    false
+}
+
+/// Gets the title of the element's preceding header, skipping the headings of "Files",
+/// "Expected Files" and "Args" sections
+pub fn get_el_title(el: Selection) -> Option<String> {
+    let mut current = el.prev_sibling();
+    while !current.is_empty() {
+        if current.is("h1, h2, h3, h4, h5, h6")
+            && !["files", "expected files", "args"]
+                .iter()
+                .any(|section| current.text().trim().eq_ignore_ascii_case(section))
+        {
+            return Some(current.text().trim().to_string());
+        }
+        current = current.prev_sibling();
+    }
+    None
+}
+
+/// Parses a markdown file and extracts a list of test cases
+#[allow(unused)]
+#[deprecated(note = "Use parse_test_markdown_html instead")]
+pub fn parse_test_markdown(file: PathBuf, src: &str) -> Result<Vec<MarcoTestCase>> {
+    let mut result = Vec::new();
+    let options = ParseOptions::default();
+    let tree = to_mdast(src, &options).map_err(|e| anyhow!("Failed to parse markdown: {}", e))?;
+    let mut iter = if let Node::Root(r) = &tree {
+        r.children.iter().peekable()
+    } else {
+        return Err(anyhow!("Expected Root node from mdast tree"));
+    };
+
+    while let Some(node) = iter.next() {
+        if let Node::ThematicBreak(_) = node {
+            if let Some(Node::Heading(h)) = iter.next() {
+                let frontmatter = h
+                    .children
+                    .iter()
+                    .filter_map(|n| match n {
+                        Node::Text(t) => Some(t.value.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("");
+
+                let header: TestHeader = serde_yml::from_str(&frontmatter)
+                    .map_err(|e| anyhow!("Failed to parse frontmatter as header: {}", e))?;
+
+                while let Some(Node::Heading(test_heading)) = iter.peek() {
+                    if test_heading
+                        .children
+                        .iter()
+                        .any(|c| matches!(c, Node::Text(t) if t.value.trim().starts_with("Test:")))
+                    {
+                        iter.next();
+                        let (input_data, input_line) = if let Some(Node::Heading(h)) = iter.next() {
+                            if h.children
+                                .iter()
+                                .any(|c| matches!(c, Node::Text(t) if t.value.trim() == "Input"))
+                            {
+                                if let Some(Node::Code(c)) = iter.next() {
+                                    (
+                                        c.value.clone(),
+                                        c.position.as_ref().map(|p| p.start.line).unwrap_or(0),
+                                    )
+                                } else {
+                                    return Err(anyhow!("Expected code block after Input heading"));
+                                }
+                            } else {
+                                return Err(anyhow!("Expected 'Input' heading"));
+                            }
+                        } else {
+                            return Err(anyhow!("Expected 'Input' heading after 'Test:'"));
+                        };
+
+                        let expected_output = if let Some(Node::Heading(h)) = iter.next() {
+                            if h.children.iter().any(|c| matches!(c, Node::Text(t) if t.value.trim() == "Expected Output")) {
+                                if let Some(Node::Code(c)) = iter.next() {
+                                    c.value.clone()
+                                } else {
+                                    return Err(anyhow!("Expected code block after Expected Output heading"));
+                                }
+                            } else {
+                                return Err(anyhow!("Expected 'Expected Output' heading"));
+                            }
+                        } else {
+                            return Err(anyhow!("Expected 'Expected Output' heading"));
+                        };
+
+                        result.push(MarcoTestCase {
+                            header: header.clone(),
+                            file: file.clone(),
+                            input_data,
+                            dialog: None,
+                            input_lang: None,
+                            raw_input: None,
+                            expected_output,
+                            expected_bytes: None,
+                            expected_sha256: None,
+                            expected_regex: false,
+                            expected_yaml: false,
+                            expected_stderr: None,
+                            block_start_line: input_line,
+                            skip: None,
+                            files: vec![],
+                            args: vec![],
+                            expected_files: vec![],
+                            expected_block: None,
+                        });
+                    } else {
+                        iter.next();
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
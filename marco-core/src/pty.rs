@@ -0,0 +1,64 @@
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::ptr;
+
+/// Rows and columns of the terminal a child runs under
+const SIZE: (u16, u16) = (24, 80);
+
+/// Runs the child under a new pseudo-terminal, which becomes its stdin, stdout, stderr and
+/// controlling terminal, in a session of its own. Input written to the terminal is not
+/// echoed into the output. Returns the controller side, which reads what the child prints
+/// and writes its input.
+pub fn attach_pty(cmd: &mut Command) -> io::Result<OwnedFd> {
+    let (mut controller, mut terminal) = (-1, -1);
+    let mut size = libc::winsize {
+        ws_row: SIZE.0,
+        ws_col: SIZE.1,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: openpty only writes the two descriptors, which are then owned here
+    let (controller, terminal) = unsafe {
+        if libc::openpty(
+            &mut controller,
+            &mut terminal,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            // `*const` on some platforms and `*mut` on others
+            &raw mut size,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        (
+            OwnedFd::from_raw_fd(controller),
+            OwnedFd::from_raw_fd(terminal),
+        )
+    };
+    // SAFETY: `termios` is plain data that tcgetattr fills in
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(terminal.as_raw_fd(), &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        termios.c_lflag &= !libc::ECHO;
+        if libc::tcsetattr(terminal.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    cmd.stdin(terminal.try_clone()?)
+        .stdout(terminal.try_clone()?)
+        .stderr(terminal);
+    // SAFETY: the closure only performs async-signal-safe syscalls
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(controller)
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::types::{MarcoTestCase, Shell};
+
+/// Checks the `requires` tools of every test once, and marks tests with missing tools as skipped
+pub fn check_requirements(tests: &mut [MarcoTestCase]) {
+    let mut checked: HashMap<String, Result<(), String>> = HashMap::new();
+    for test in tests.iter_mut() {
+        for requirement in &test.header.requires {
+            let status = checked
+                .entry(requirement.clone())
+                .or_insert_with(|| check_requirement(requirement));
+            if let Err(reason) = status
+                && test.skip.is_none()
+            {
+                test.skip = Some(reason.clone());
+            }
+        }
+    }
+}
+
+/// Checks a single requirement such as `jq` or `python3>=3.10`
+fn check_requirement(requirement: &str) -> Result<(), String> {
+    let (tool, constraint) = parse_requirement(requirement);
+    if find_on_path(tool).is_none() {
+        return Err(format!("requires {} (not found on PATH)", tool));
+    }
+    let Some((op, wanted)) = constraint else {
+        return Ok(());
+    };
+
+    let found = tool_version(tool).ok_or_else(|| {
+        format!(
+            "requires {} (could not read `{} --version`)",
+            requirement, tool
+        )
+    })?;
+    let ordering = compare_versions(&found, wanted);
+    let ok = match op {
+        ">=" => ordering.is_ge(),
+        ">" => ordering.is_gt(),
+        "<=" => ordering.is_le(),
+        "<" => ordering.is_lt(),
+        _ => ordering.is_eq(),
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("requires {} (found {})", requirement, found))
+    }
+}
+
+/// Splits `python3>=3.10` into the tool name and an optional (operator, version) constraint
+fn parse_requirement(requirement: &str) -> (&str, Option<(&str, &str)>) {
+    let requirement = requirement.trim();
+    for op in [">=", "<=", "==", ">", "<", "="] {
+        if let Some((tool, version)) = requirement.split_once(op) {
+            return (tool.trim(), Some((op, version.trim())));
+        }
+    }
+    (requirement, None)
+}
+
+/// Finds the program of a shell, looking once per run
+pub fn find_shell(shell: Shell) -> Option<PathBuf> {
+    static FOUND: [OnceLock<Option<PathBuf>>; 4] = [const { OnceLock::new() }; 4];
+    FOUND[shell as usize]
+        .get_or_init(|| {
+            find_on_path(shell.name()).or_else(|| {
+                // Git for Windows does not put its bash on PATH by default
+                let program_files = env::var_os("ProgramFiles").filter(|_| cfg!(windows))?;
+                let git_bash = PathBuf::from(program_files)
+                    .join("Git")
+                    .join("bin")
+                    .join("bash.exe");
+                (shell == Shell::Bash && git_bash.is_file()).then_some(git_bash)
+            })
+        })
+        .clone()
+}
+
+/// Finds an executable on PATH, honoring PATHEXT on Windows
+fn find_on_path(tool: &str) -> Option<PathBuf> {
+    let extensions: Vec<String> = if cfg!(windows) {
+        env::var("PATHEXT")
+            .unwrap_or_else(|_| ".EXE;.CMD;.BAT;.COM".to_owned())
+            .split(';')
+            .map(str::to_owned)
+            .chain([String::new()])
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path).find_map(|dir| {
+        extensions.iter().find_map(|ext| {
+            let candidate = dir.join(format!("{}{}", tool, ext));
+            candidate.is_file().then_some(candidate)
+        })
+    })
+}
+
+/// Runs `tool --version` and extracts the first dotted version number from its output
+fn tool_version(tool: &str) -> Option<String> {
+    let output = Command::new(tool).arg("--version").output().ok()?;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let re = Regex::new(r"\d+(\.\d+)+").ok()?;
+    re.find(&text).map(|m| m.as_str().to_owned())
+}
+
+/// Compares dotted versions numerically, treating missing components as 0
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |v: &str| -> Vec<u64> {
+        v.split('.')
+            .map(|part| {
+                part.chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect::<String>()
+                    .parse()
+                    .unwrap_or(0)
+            })
+            .collect()
+    };
+    let (a, b) = (parse(a), parse(b));
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| {
+            let x = a.get(i).copied().unwrap_or(0);
+            let y = b.get(i).copied().unwrap_or(0);
+            x.cmp(&y)
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
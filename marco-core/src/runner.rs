@@ -0,0 +1,2086 @@
+use crate::compare::{Comparators, CompareInput, Comparison};
+use crate::dialog::{DialogStep, run_dialog};
+use crate::isolation::disable_network;
+use crate::jobs::JobSlots;
+use crate::mask::Mask;
+use crate::memory::ChildMonitor;
+use crate::normalize::{Normalizer, TextOptions};
+#[cfg(unix)]
+use crate::pty::attach_pty;
+use crate::requirements::find_shell;
+use crate::sandbox::{InputFile, Sandbox, TestFiles, copy_dir};
+use crate::session::Session;
+use crate::types::{
+    Attempt, Capture, InputAs, LineEndings, MarcoTestCase, RunnerMode, Shell, TestResult,
+};
+use crate::util::*;
+use crate::worker::Workers;
+use rayon::prelude::*;
+use similar::Algorithm;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+/// How tests are run. The CLI fills these from its options and config files; code embedding
+/// marco sets them directly.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Runner for tests whose header sets none and whose Input language has no `lang_runners`
+    /// entry
+    pub runner: Option<String>,
+    /// Use `runner` even for tests whose header sets one
+    pub force_runner: bool,
+    /// Runners for tests without one, by Input fence language or extension; later ones win, and
+    /// all of them beat `runner`
+    pub lang_runners: Vec<(String, String)>,
+    /// Runner commands by alias; a runner that is exactly an alias is replaced
+    pub runner_aliases: BTreeMap<String, String>,
+    /// Fail tests that would otherwise only produce warnings (e.g. empty Expected Output)
+    pub strict: bool,
+    /// Seed from which each test's MARCO_SEED / `{seed}` is derived
+    pub seed: Option<u64>,
+    /// Environment variables for every test's runner, unless its header's `env` sets them
+    pub env: Vec<(String, String)>,
+    /// Dotenv files loaded into every test's environment before `env`
+    pub env_files: Vec<PathBuf>,
+    /// Start runners with only `pass_env`, `env` and marco's own variables in their environment,
+    /// unless a header sets `clean_env`
+    pub clean_env: bool,
+    /// Variables of marco's environment that runners still get under `clean_env`
+    pub pass_env: Vec<String>,
+    /// Run tests without network access unless their header sets `network: true`
+    pub no_network: bool,
+    /// Remove terminal escape sequences before comparing, unless a header sets `strip_ansi`
+    pub strip_ansi: bool,
+    /// Flag tests that take longer than this as slow
+    pub slow_threshold: Option<Duration>,
+    /// Kill a test's runner after this long, unless its header sets `timeout`
+    pub timeout: Option<Duration>,
+    /// Rerun a failing test up to this many times, unless its header sets `retries`
+    pub retries: Option<u32>,
+    /// Run each passing test this many times, failing it if the runs differ
+    pub repeat: Option<u32>,
+    /// Cancel the pending and running tests of `run_suite` once one fails
+    pub fail_fast: bool,
+    /// Write the output, diff and files of failing tests into this directory
+    pub artifacts_dir: Option<PathBuf>,
+    /// Algorithm of the diffs written into `artifacts_dir`
+    pub diff_algorithm: Algorithm,
+    /// Point in time after which running tests are killed and pending ones not started
+    pub deadline: Option<Instant>,
+    /// Case and whitespace options for tests whose header does not set them
+    pub text_options: TextOptions,
+    /// Comparators a test's `compare` header can select, the built-in ones unless more are
+    /// registered
+    pub comparators: Comparators,
+    /// Set once a test fails under `fail_fast`; pending tests are then skipped
+    pub cancelled: Arc<AtomicBool>,
+    /// Limits how many tests and hooks have runners running at once, while any number of
+    /// threads parse, compare and report
+    pub jobs: Option<Arc<JobSlots>>,
+    /// Runners of tests with `runner_mode: worker`, started as they are needed
+    pub workers: Arc<Workers>,
+    /// Shell that runs the runner commands of tests whose header sets none
+    pub shell: Option<Shell>,
+}
+
+impl RunOptions {
+    /// Runner for an Input block language from `lang_runners`
+    pub fn runner_for_lang(&self, lang: &str) -> Option<&str> {
+        let custom = self.lang_runners.iter().rev().find(|(l, _)| l == lang);
+        custom.map(|(_, cmd)| cmd.as_str())
+    }
+
+    /// Expands a runner command that is exactly one of the configured aliases
+    pub fn resolve_alias<'a>(&'a self, cmd: &'a str) -> &'a str {
+        self.runner_aliases
+            .get(cmd.trim())
+            .map_or(cmd, String::as_str)
+    }
+
+    /// Whether `fail_fast` stopped the run
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Whether the run `deadline` has passed
+    pub fn deadline_exceeded(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Built-in runners for common Input block languages
+fn default_lang_runner(lang: &str) -> Option<&'static str> {
+    match lang {
+        "py" | "python" => Some("python3 -"),
+        "sh" | "shell" => Some("sh"),
+        "bash" => Some("bash"),
+        "js" | "javascript" => Some("node"),
+        "rb" | "ruby" => Some("ruby"),
+        "pl" | "perl" => Some("perl"),
+        "ps1" | "powershell" => Some("powershell -NoProfile -Command -"),
+        _ => None,
+    }
+}
+
+/// Receives the tests of `run_suite` as they start and finish. Tests run in parallel, so both
+/// are called from several threads at once; `id`, the test's index, tells them apart.
+pub trait Observer: Sync {
+    fn test_started(&self, _test: &MarcoTestCase, _id: usize) {}
+
+    fn test_finished(&self, _result: &TestResult, _id: usize) {}
+}
+
+/// Observes nothing
+impl Observer for () {}
+
+/// What `run_suite` returns
+#[derive(Debug)]
+pub struct SuiteResults {
+    /// One result per test, in the order the tests were given
+    pub results: Vec<TestResult>,
+    /// Index of the failure that stopped the run under `fail_fast`
+    pub first_failure: Option<usize>,
+    /// Files whose `after_all` failed, with the error, in file order. They fail the run but none
+    /// of the file's tests, which had all finished.
+    pub after_all_errors: Vec<(PathBuf, String)>,
+}
+
+/// Runs the tests on rayon's thread pool, keeping tests of the same `serial` group, and those
+/// writing Files into the same directory, apart and running those of a `session` file in order,
+/// against one runner process. Under `fail_fast` the first failure cancels the tests that are
+/// pending or still running.
+pub fn run_suite(
+    tests: &[MarcoTestCase],
+    opts: &RunOptions,
+    observer: &dyn Observer,
+) -> SuiteResults {
+    let serial = SerialLocks::new(tests);
+    let hooks = FileHooks::new(tests);
+    let first_failure = AtomicUsize::new(usize::MAX);
+    let after_all_errors = Mutex::new(vec![]);
+    let run_one = |i: usize, test: &MarcoTestCase, session: Option<&Session>, last: bool| {
+        let _guard = serial.lock(test);
+        observer.test_started(test, i);
+        let result = match hooks.before(test, opts) {
+            Ok(()) => run_test(test, opts, session),
+            Err(e) => TestResult::failure(test, format!("before_all failed: {}", e)),
+        };
+        // A session's runner is done with its last test, before `after_all` cleans up
+        if last && let Some(session) = session {
+            session.finish();
+        }
+        // The file's last test to finish runs its `after_all`, whose failure is the file's
+        if let Err(e) = hooks.after(test, opts) {
+            let mut errors = after_all_errors
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            errors.push((test.file.clone(), e));
+        }
+        // Only the first failure cancels the run; it is the one reported
+        if opts.fail_fast && result.failed() && !opts.cancelled.swap(true, Ordering::Relaxed) {
+            first_failure.store(i, Ordering::Relaxed);
+        }
+        observer.test_finished(&result, i);
+        (i, result)
+    };
+    // The tests of a session file share a runner process, so they run in order on one thread
+    let mut units: Vec<Vec<(usize, &MarcoTestCase)>> = vec![];
+    let mut session_units: HashMap<&Path, usize> = HashMap::new();
+    for (i, test) in tests.iter().enumerate() {
+        if test.header.session {
+            let unit = *session_units.entry(&test.file).or_insert_with(|| {
+                units.push(vec![]);
+                units.len() - 1
+            });
+            units[unit].push((i, test));
+        } else {
+            units.push(vec![(i, test)]);
+        }
+    }
+    // Bridge from a sequential iterator so tests are dispatched in collection order
+    let mut results: Vec<_> = units
+        .into_iter()
+        .par_bridge()
+        .flat_map_iter(|unit| {
+            let session = unit
+                .first()
+                .filter(|(_, test)| test.header.session)
+                .and_then(|(_, test)| {
+                    let delimiter = test.header.session_delimiter.as_deref()?;
+                    (!delimiter.is_empty())
+                        .then(|| Session::new(delimiter, test.header.session_prompt))
+                });
+            let last = unit.len() - 1;
+            unit.into_iter()
+                .enumerate()
+                .map(|(n, (i, test))| run_one(i, test, session.as_ref(), n == last))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    results.sort_by_key(|(i, _)| *i);
+    let mut after_all_errors = after_all_errors
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    after_all_errors.sort();
+    // Workers are started again by the next run if there is one
+    opts.workers.finish();
+    SuiteResults {
+        results: results.into_iter().map(|(_, result)| result).collect(),
+        first_failure: opts
+            .is_cancelled()
+            .then(|| first_failure.load(Ordering::Relaxed)),
+        after_all_errors,
+    }
+}
+
+/// Named locks that keep tests in the same `serial` group from running concurrently, and
+/// tests writing a Files section into the same directory outside a sandbox
+pub struct SerialLocks {
+    groups: HashMap<String, Mutex<()>>,
+    dirs: HashMap<PathBuf, Mutex<()>>,
+}
+
+impl SerialLocks {
+    pub fn new(tests: &[MarcoTestCase]) -> Self {
+        SerialLocks {
+            groups: tests
+                .iter()
+                .filter_map(|t| t.header.serial.clone())
+                .map(|group| (group, Mutex::new(())))
+                .collect(),
+            dirs: tests
+                .iter()
+                .filter_map(files_dir)
+                .map(|dir| (dir, Mutex::new(())))
+                .collect(),
+        }
+    }
+
+    /// Holds the lock of the test's serial group and that of the directory its Files are
+    /// written to, if it has them. The directory's lock is taken last, so holding it never
+    /// waits for another lock.
+    pub fn lock(
+        &self,
+        test: &MarcoTestCase,
+    ) -> (Option<MutexGuard<'_, ()>>, Option<MutexGuard<'_, ()>>) {
+        fn lock(lock: &Mutex<()>) -> MutexGuard<'_, ()> {
+            lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+        let group = test
+            .header
+            .serial
+            .as_ref()
+            .and_then(|group| self.groups.get(group));
+        let group = group.map(lock);
+        let dir = files_dir(test).and_then(|dir| self.dirs.get(&dir));
+        (group, dir.map(lock))
+    }
+}
+
+/// Working directory a test's Files section is written to, unless it has none or a sandbox
+fn files_dir(test: &MarcoTestCase) -> Option<PathBuf> {
+    if test.files.is_empty() || test.header.sandbox {
+        return None;
+    }
+    let dir = match &test.header.cwd {
+        Some(cwd) => test_dir(test).join(cwd),
+        None => test_dir(test).to_path_buf(),
+    };
+    // The same directory may be reached through different paths
+    Some(dir.canonicalize().unwrap_or(dir))
+}
+
+/// Runs each file's `before_all` command before the first of its tests that is not skipped,
+/// and its `after_all` command once all of its tests are done
+struct FileHooks<'a>(HashMap<&'a Path, FileHookState>);
+
+struct FileHookState {
+    /// Outcome of `before_all`; tests of the file wait for it to finish
+    before: OnceLock<Result<(), String>>,
+    /// Tests of the file that have not finished
+    remaining: AtomicUsize,
+}
+
+impl<'a> FileHooks<'a> {
+    fn new(tests: &'a [MarcoTestCase]) -> Self {
+        let mut files: HashMap<&Path, FileHookState> = HashMap::new();
+        for test in tests {
+            files
+                .entry(&test.file)
+                .or_insert_with(|| FileHookState {
+                    before: OnceLock::new(),
+                    remaining: AtomicUsize::new(0),
+                })
+                .remaining
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        FileHooks(files)
+    }
+
+    /// Runs the file's `before_all` unless it already ran, returning how it went. Skipped
+    /// tests do not trigger it.
+    fn before(&self, test: &MarcoTestCase, opts: &RunOptions) -> Result<(), String> {
+        let Some(state) = self.0.get(test.file.as_path()) else {
+            return Ok(());
+        };
+        if skip_reason(test, opts).is_some() {
+            return Ok(());
+        }
+        state
+            .before
+            .get_or_init(|| match &test.header.before_all {
+                Some(cmd) => run_hook(cmd.for_current_platform(), test, opts),
+                None => Ok(()),
+            })
+            .clone()
+    }
+
+    /// Marks the test as done, running the file's `after_all` after its last test if any of
+    /// them ran
+    fn after(&self, test: &MarcoTestCase, opts: &RunOptions) -> Result<(), String> {
+        let Some(state) = self.0.get(test.file.as_path()) else {
+            return Ok(());
+        };
+        if state.remaining.fetch_sub(1, Ordering::AcqRel) != 1 || state.before.get().is_none() {
+            return Ok(());
+        }
+        match &test.header.after_all {
+            Some(cmd) => run_hook(cmd.for_current_platform(), test, opts),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs a `before_all`/`after_all` command in the directory and environment of the file's
+/// tests, failing unless it exits with 0
+fn run_hook(cmd: &str, test: &MarcoTestCase, opts: &RunOptions) -> Result<(), String> {
+    let dir = hook_dir(test);
+    // Taken before the timeout starts, so waiting for a slot does not count against it
+    let _slot = opts.jobs.as_deref().map(JobSlots::acquire);
+    let timeout_at = test
+        .header
+        .timeout
+        .or(opts.timeout)
+        .map(|timeout| Instant::now() + timeout);
+    let child = hook_config(&dir, test, opts, timeout_at)?;
+    match run_command(cmd, &[], &child)? {
+        ChildOutcome::Exited(output, _) if output.status.success() => Ok(()),
+        ChildOutcome::Exited(output, _) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut error = format!(
+                "{:?} exited with {}",
+                cmd,
+                describe_exit(output.status.code())
+            );
+            if !stderr.trim().is_empty() {
+                error = format!("{}: {}", error, stderr.trim());
+            }
+            Err(error)
+        }
+        ChildOutcome::Killed(_) => Err(format!("{:?} was killed at its deadline", cmd)),
+    }
+}
+
+/// Working directory of a file's hooks: that of its tests, outside any sandbox
+fn hook_dir(test: &MarcoTestCase) -> PathBuf {
+    match &test.header.cwd {
+        Some(cwd) => test_dir(test).join(cwd),
+        None => test_dir(test).to_path_buf(),
+    }
+}
+
+/// Runs commands outside of a test, like hooks, in `dir` with the test's environment. They are
+/// killed at `deadline` or the run's deadline, whichever comes first.
+fn hook_config<'a>(
+    dir: &'a Path,
+    test: &MarcoTestCase,
+    opts: &RunOptions,
+    deadline: Option<Instant>,
+) -> Result<ChildConfig<'a>, String> {
+    let header = &test.header;
+    let clean_env = header.clean_env.unwrap_or(opts.clean_env);
+    let mut env = if clean_env {
+        passed_env(test, opts)
+    } else {
+        vec![]
+    };
+    for (key, value) in custom_env(test, opts)? {
+        env.retain(|(k, _)| *k != key);
+        env.push((key, value));
+    }
+    Ok(ChildConfig {
+        dir,
+        env,
+        clean_env,
+        network: header.network.unwrap_or(!opts.no_network),
+        deadline: match (opts.deadline, deadline) {
+            (Some(run), Some(hook)) => Some(run.min(hook)),
+            (run, hook) => run.or(hook),
+        },
+        cancelled: None,
+        combined_output: false,
+        pty: false,
+        shell: runner_shell(test, opts),
+    })
+}
+
+/// How long `runner_version` waits for a runner to print its version
+const VERSION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// First line of `prog --version`, run in the directory and environment of `test`, checking
+/// stderr for tools that print it there. `None` if it fails or takes over `VERSION_TIMEOUT`.
+pub fn runner_version(prog: &str, test: &MarcoTestCase, opts: &RunOptions) -> Option<String> {
+    let dir = hook_dir(test);
+    let child = hook_config(&dir, test, opts, Some(Instant::now() + VERSION_TIMEOUT)).ok()?;
+    let cmd = format!("{} --version", quote_arg(prog, child.shell));
+    let ChildOutcome::Exited(output, _) = run_command(&cmd, &[], &child).ok()? else {
+        return None;
+    };
+    if !output.status.success() {
+        return None;
+    }
+    [&output.stdout, &output.stderr]
+        .into_iter()
+        .map(|out| String::from_utf8_lossy(out).trim().to_owned())
+        .find(|text| !text.is_empty())
+        .and_then(|text| text.lines().next().map(str::to_owned))
+}
+
+/// Skip reason of tests not run, or killed, because `--fail-fast` stopped the run
+pub const CANCELLED_REASON: &str = "cancelled by --fail-fast";
+
+/// Skip reason of tests that had not started when `--run-timeout` aborted the run
+pub const NOT_RUN_REASON: &str = "not run: --run-timeout exceeded";
+
+/// Runs a single test case, rerunning it while it fails up to its `retries`
+pub fn run_test_case(test: &MarcoTestCase, opts: &RunOptions) -> TestResult {
+    run_test(test, opts, None)
+}
+
+/// Runs a test case, writing its input to the `session` runner if it has one
+fn run_test(test: &MarcoTestCase, opts: &RunOptions, session: Option<&Session>) -> TestResult {
+    // Read once, as the env files do not change between attempts
+    let custom = match custom_env(test, opts) {
+        Ok(custom) => custom,
+        Err(e) => return TestResult::failure(test, e),
+    };
+    let mask = match Mask::new(&test.header.mask, &custom) {
+        Ok(mask) => mask,
+        Err(e) => return TestResult::failure(test, e),
+    };
+    let retries = test.header.retries.or(opts.retries).unwrap_or(0);
+    let mut attempts = vec![];
+    let mut workspace = None;
+    let mut result = loop {
+        let result = run_attempt(test, opts, session, &custom, &mut workspace);
+        // A cancelled or aborted run leaves no time for another attempt
+        if !result.failed()
+            || attempts.len() as u32 >= retries
+            || opts.is_cancelled()
+            || opts.deadline_exceeded()
+        {
+            break result;
+        }
+        attempts.push(Attempt::of(&result));
+    };
+    if !attempts.is_empty() {
+        result.flaky = result.passed;
+        attempts.push(Attempt::of(&result));
+        result.attempts = attempts;
+    }
+    if let Some(repeat) = opts.repeat
+        && result.passed
+    {
+        repeat_test(
+            test,
+            opts,
+            session,
+            &custom,
+            &mut result,
+            &mut workspace,
+            repeat,
+        );
+    }
+    mask.apply_to_result(&mut result);
+    if result.failed()
+        && let Some(root) = &opts.artifacts_dir
+    {
+        match collect_artifacts(root, test, &result, workspace.as_ref(), opts.diff_algorithm) {
+            Ok(dir) => result.artifacts = Some(dir),
+            Err(e) => result
+                .warnings
+                .push(format!("Failed to collect artifacts: {}", e)),
+        }
+    }
+    result
+}
+
+/// Where a test's runner ran, kept after the run so its files can be collected as artifacts
+struct Workspace {
+    dir: PathBuf,
+    sandbox: Option<Sandbox>,
+}
+
+/// Runs a passed test until it has run `repeat` times, failing it with the first failed run,
+/// or else with a diff of the first run whose output or exit code differed from the first's.
+/// `workspace` ends up holding the failed run's.
+fn repeat_test(
+    test: &MarcoTestCase,
+    opts: &RunOptions,
+    session: Option<&Session>,
+    custom: &[(String, String)],
+    result: &mut TestResult,
+    workspace: &mut Option<Workspace>,
+    repeat: u32,
+) {
+    let mut failed_run = None;
+    let mut diverged_run = None;
+    for run in 2..=repeat {
+        if opts.is_cancelled() || opts.deadline_exceeded() {
+            break;
+        }
+        let mut run_workspace = None;
+        let other = run_attempt(test, opts, session, custom, &mut run_workspace);
+        if other.skipped.is_some() {
+            break;
+        }
+        if other.stdout != result.stdout || other.exit_code != result.exit_code {
+            result.diverged.push(run);
+            diverged_run.get_or_insert(other.stdout.clone());
+        }
+        if other.failed() && failed_run.is_none() {
+            failed_run = Some((run, other));
+            *workspace = run_workspace;
+        }
+    }
+    let diverged = std::mem::take(&mut result.diverged);
+    if let Some((run, other)) = failed_run {
+        let error = other.error.as_deref().unwrap_or("Test failed");
+        *result = TestResult {
+            error: Some(format!("Run {} of {} failed: {}", run, repeat, error)),
+            diverged,
+            ..other
+        };
+    } else if let Some(stdout) = diverged_run {
+        let runs: Vec<_> = diverged.iter().map(u32::to_string).collect();
+        result.passed = false;
+        result.error = Some(format!(
+            "Output is not deterministic: runs {} of {} differed from run 1",
+            runs.join(", "),
+            repeat
+        ));
+        result.expected = result.stdout.trim().to_string();
+        result.actual = stdout.trim().to_string();
+        result.diverged = diverged;
+    }
+}
+
+/// Runs the test once, timing it against the slow threshold and `max_duration`
+fn run_attempt(
+    test: &MarcoTestCase,
+    opts: &RunOptions,
+    session: Option<&Session>,
+    custom: &[(String, String)],
+    workspace: &mut Option<Workspace>,
+) -> TestResult {
+    let start = Instant::now();
+    let mut result = execute_test_case(test, opts, session, custom, workspace);
+    result.duration = start.elapsed();
+    result.slow = opts
+        .slow_threshold
+        .is_some_and(|threshold| result.duration > threshold);
+    if let Some(max) = test.header.max_duration
+        && result.passed
+        && result.duration > max
+    {
+        result.passed = false;
+        result.too_slow = true;
+        result.error = Some(format!(
+            "Too slow: took {:.2?}, exceeding max_duration of {:?}",
+            result.duration, max
+        ));
+    }
+    result
+}
+
+/// The runner steps for a test, with the exit code each must return
+pub fn resolve_runner<'a>(
+    test: &'a MarcoTestCase,
+    opts: &'a RunOptions,
+) -> Option<Vec<(&'a str, Option<i32>)>> {
+    if opts.force_runner
+        && let Some(cmd) = &opts.runner
+    {
+        return Some(vec![(opts.resolve_alias(cmd), None)]);
+    }
+    // Pick runner: prefer test.header.runner, fallback to default_runner if present
+    match &test.header.runner {
+        Some(runner) => Some(
+            runner
+                .steps()
+                .into_iter()
+                .map(|(cmd, code)| (opts.resolve_alias(cmd), code))
+                .collect(),
+        ),
+        None => {
+            // No runner in YAML: a runner mapped to the Input block's language, then
+            // default_runner, then a built-in runner for the language
+            let lang = test.input_lang.as_deref();
+            let def_cmd = lang
+                .and_then(|lang| opts.runner_for_lang(lang))
+                .or(opts.runner.as_deref())
+                .or_else(|| lang.and_then(default_lang_runner))?;
+            Some(vec![(opts.resolve_alias(def_cmd), None)])
+        }
+    }
+}
+
+/// The variables a test sets for its runner, from `--env-file`, `--env`, the header's
+/// `env_file` and its `env`, in that order
+fn custom_env(test: &MarcoTestCase, opts: &RunOptions) -> Result<Vec<(String, String)>, String> {
+    let env_file = |path: &Path| {
+        fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read env file {:?}: {}", path, e))
+            .and_then(|src| {
+                parse_dotenv(&src).map_err(|e| format!("Invalid env file {:?}: {}", path, e))
+            })
+    };
+    let mut env = vec![];
+    for path in &opts.env_files {
+        env.extend(env_file(path)?);
+    }
+    env.extend(opts.env.iter().cloned());
+    if let Some(path) = &test.header.env_file {
+        let dir = test_dir(test);
+        env.extend(env_file(&dir.join(path))?);
+    }
+    env.extend(
+        test.header
+            .env
+            .iter()
+            .map(|(key, value)| (key.clone(), value.to_string())),
+    );
+    Ok(env)
+}
+
+/// The test with each `${VAR}` in the blocks it sends or compares replaced by the value of
+/// `VAR` from its environment variables, or else from marco's environment
+fn interpolate_blocks(
+    test: &MarcoTestCase,
+    custom: &[(String, String)],
+) -> Result<MarcoTestCase, String> {
+    // Later variables override earlier ones, as when they are set
+    let lookup = |name: &str| {
+        custom
+            .iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var(name).ok())
+    };
+    let interpolate = |text: &str, block: &str| {
+        interpolate_env(text, lookup).map_err(|e| format!("{}: {}", block, e))
+    };
+    let files = |files: &[(String, String)], block: &str| {
+        files
+            .iter()
+            .map(|(path, content)| {
+                interpolate(content, &format!("{} {:?}", block, path))
+                    .map(|content| (path.clone(), content))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let dialog = match &test.dialog {
+        Some(steps) => Some(
+            steps
+                .iter()
+                .map(|step| match step {
+                    DialogStep::Send(line) => {
+                        interpolate(line, "Input block").map(DialogStep::Send)
+                    }
+                    DialogStep::Expect { text, timeout } => {
+                        interpolate(text, "Expected Output block").map(|text| DialogStep::Expect {
+                            text,
+                            timeout: *timeout,
+                        })
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        None => None,
+    };
+    Ok(MarcoTestCase {
+        input_data: interpolate(&test.input_data, "Input block")?,
+        dialog,
+        expected_output: interpolate(&test.expected_output, "Expected Output block")?,
+        expected_stderr: test
+            .expected_stderr
+            .as_deref()
+            .map(|stderr| interpolate(stderr, "Expected Stderr block"))
+            .transpose()?,
+        files: files(&test.files, "File")?,
+        args: test
+            .args
+            .iter()
+            .map(|arg| interpolate(arg, "Args block"))
+            .collect::<Result<_, _>>()?,
+        expected_files: files(&test.expected_files, "Expected File")?,
+        ..test.clone()
+    })
+}
+
+/// Why a test is skipped without running, if it is
+fn skip_reason(test: &MarcoTestCase, opts: &RunOptions) -> Option<String> {
+    if opts.deadline_exceeded() {
+        return Some(NOT_RUN_REASON.to_string());
+    }
+    if opts.is_cancelled() {
+        return Some(CANCELLED_REASON.to_string());
+    }
+    if let Some(reason) = &test.skip {
+        return Some(reason.clone());
+    }
+    let header = &test.header;
+    if !header.only_on.is_empty() && !header.only_on.iter().any(|p| platform_matches(p)) {
+        return Some(format!("only runs on {}", header.only_on.join(", ")));
+    }
+    if let Some(platform) = header.skip_on.iter().find(|p| platform_matches(p)) {
+        return Some(format!("excluded on {}", platform));
+    }
+    None
+}
+
+/// The variables of marco's own environment a runner keeps under `clean_env`
+fn passed_env(test: &MarcoTestCase, opts: &RunOptions) -> Vec<(String, String)> {
+    opts.pass_env
+        .iter()
+        .chain(&test.header.pass_env)
+        .filter_map(|key| Some((key.clone(), std::env::var(key).ok()?)))
+        .collect()
+}
+
+fn execute_test_case(
+    test: &MarcoTestCase,
+    opts: &RunOptions,
+    session: Option<&Session>,
+    custom: &[(String, String)],
+    workspace: &mut Option<Workspace>,
+) -> TestResult {
+    if let Some(reason) = skip_reason(test, opts) {
+        return TestResult::skipped(test, reason);
+    }
+
+    // Everything below sees the interpolated blocks
+    let interpolated;
+    let test = if test.header.interpolate {
+        match interpolate_blocks(test, custom) {
+            Ok(test) => {
+                interpolated = test;
+                &interpolated
+            }
+            Err(e) => return TestResult::failure(test, e),
+        }
+    } else {
+        test
+    };
+    let header = &test.header;
+
+    if header.session {
+        if header
+            .session_delimiter
+            .as_deref()
+            .is_none_or(str::is_empty)
+        {
+            return TestResult::failure(test, "`session: true` needs a `session_delimiter`");
+        }
+        if header.sandbox {
+            return TestResult::failure(test, "`sandbox` cannot be combined with `session: true`");
+        }
+        if test.dialog.is_some() {
+            return TestResult::failure(test, "A dialog cannot be used with `session: true`");
+        }
+        if !test.args.is_empty() {
+            return TestResult::failure(
+                test,
+                "An Args section cannot be used with `session: true`",
+            );
+        }
+        if header.input_as == InputAs::File {
+            return TestResult::failure(
+                test,
+                "`input_as: file` cannot be combined with `session: true`",
+            );
+        }
+    }
+    if header.runner_mode == RunnerMode::Worker {
+        let conflict = [
+            (header.session, "`session: true`"),
+            (header.sandbox, "`sandbox`"),
+            (header.pty, "`pty: true`"),
+            (header.capture == Capture::Combined, "`capture: combined`"),
+            (test.dialog.is_some(), "A dialog"),
+            (header.input_as == InputAs::File, "`input_as: file`"),
+        ]
+        .into_iter()
+        .find(|(set, _)| *set);
+        if let Some((_, option)) = conflict {
+            return TestResult::failure(
+                test,
+                format!("{} cannot be combined with `runner_mode: worker`", option),
+            );
+        }
+    }
+    if header.input_as == InputAs::File && test.dialog.is_some() {
+        return TestResult::failure(test, "A dialog cannot be used with `input_as: file`");
+    }
+    if header.exec && header.shell.is_some() {
+        return TestResult::failure(test, "`exec: true` cannot be combined with `shell`");
+    }
+    if header.capture == Capture::Combined && test.expected_stderr.is_some() {
+        return TestResult::failure(
+            test,
+            "An Expected Stderr block cannot be used with `capture: combined`",
+        );
+    }
+    let normalizer = match Normalizer::new(&header.normalize) {
+        Ok(normalizer) => normalizer,
+        Err(e) => return TestResult::failure(test, e),
+    };
+    let text = header.text.or(opts.text_options);
+    // Regex lines can use `(?i)` instead; lowercasing them would change their meaning
+    let text = if test.expected_regex {
+        TextOptions {
+            ignore_case: None,
+            ..text
+        }
+    } else {
+        text
+    };
+    let ignore_paths = match header
+        .ignore_paths
+        .iter()
+        .map(|path| JsonPath::parse(path))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(paths) => paths,
+        Err(e) => return TestResult::failure(test, e),
+    };
+    if opts.strict && test.has_empty_expected() {
+        return TestResult::failure(
+            test,
+            "Expected Output block is empty (rejected in strict mode)",
+        );
+    }
+
+    let Some(steps) = resolve_runner(test, opts) else {
+        return TestResult::failure(
+            test,
+            "No 'runner' command provided in test front matter or args",
+        );
+    };
+    let seed = test_seed(opts.seed.unwrap_or_default(), &test.file, &header.name);
+    let steps: Vec<(String, Option<i32>)> = steps
+        .into_iter()
+        .map(|(cmd, exit_code)| (cmd.replace("{seed}", &seed.to_string()), exit_code))
+        .collect();
+    if header.input_as == InputAs::File
+        && steps
+            .last()
+            .is_some_and(|(cmd, _)| !cmd.contains("{{input_file}}"))
+    {
+        return TestResult::failure(
+            test,
+            "`input_as: file` needs `{{input_file}}` in the runner command",
+        );
+    }
+    // Both are removed when the test is done
+    let input_file = match header.input_as {
+        InputAs::Stdin => None,
+        InputAs::File => match InputFile::new(test.stdin()) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                return TestResult::failure(test, format!("Failed to write the input file: {}", e));
+            }
+        },
+    };
+    let tmpdir = if steps.iter().any(|(cmd, _)| cmd.contains("{{tmpdir}}")) {
+        match Sandbox::new(None) {
+            Ok(tmpdir) => Some(tmpdir),
+            Err(e) => {
+                return TestResult::failure(
+                    test,
+                    format!("Failed to create {{{{tmpdir}}}}: {}", e),
+                );
+            }
+        }
+    } else {
+        None
+    };
+    let vars = match template_vars(
+        test,
+        &steps,
+        tmpdir.as_ref().map(Sandbox::path),
+        input_file.as_ref().map(InputFile::path),
+    ) {
+        Ok(vars) => vars,
+        Err(e) => return TestResult::failure(test, e),
+    };
+    let steps: Vec<(String, Option<i32>)> = steps
+        .into_iter()
+        .map(|(cmd, exit_code)| {
+            let cmd = expand_template(&cmd, &vars, runner_shell(test, opts));
+            (cmd, exit_code)
+        })
+        .collect();
+    let Some(((runner_cmd, final_exit_code), setup)) = steps.split_last() else {
+        return TestResult::failure(test, "The 'runner' step list is empty");
+    };
+    let stdin = match input_file {
+        Some(_) => &[][..],
+        None => test.stdin(),
+    };
+    let runner_cmd = &append_args(runner_cmd, &test.args, runner_shell(test, opts));
+
+    let clean_env = header.clean_env.unwrap_or(opts.clean_env);
+    // A clean environment only keeps the allowed variables of marco's own environment
+    let mut env = if clean_env {
+        passed_env(test, opts)
+    } else {
+        vec![]
+    };
+    env.push(("MARCO_SEED".to_string(), seed.to_string()));
+    if let Some(fake_time) = &header.fake_time {
+        match fake_time.env() {
+            Ok(vars) => env.extend(
+                vars.into_iter()
+                    .map(|(key, value)| (key.to_string(), value)),
+            ),
+            Err(e) => return TestResult::failure(test, e),
+        }
+    }
+    // They override the variables above and each other
+    for (key, value) in custom {
+        env.retain(|(k, _)| k != key);
+        env.push((key.clone(), value.clone()));
+    }
+    let test_dir = test_dir(test);
+    // `join` keeps an absolute path as it is
+    let fixtures = header
+        .fixtures
+        .as_ref()
+        .map(|fixtures| test_dir.join(fixtures));
+    if fixtures.is_some() && !header.sandbox {
+        return TestResult::failure(test, "`fixtures` can only be used with `sandbox: true`");
+    }
+    if header.cwd.is_some() && header.sandbox {
+        return TestResult::failure(test, "`cwd` cannot be combined with `sandbox: true`");
+    }
+    // Removed with everything in it once the test's artifacts are collected
+    let sandbox = if header.sandbox {
+        match Sandbox::new(fixtures.as_deref()) {
+            Ok(sandbox) => Some(sandbox),
+            Err(e) => {
+                return TestResult::failure(test, format!("Failed to create sandbox: {}", e));
+            }
+        }
+    } else {
+        None
+    };
+    let dir = match (&sandbox, &header.cwd) {
+        (Some(sandbox), _) => sandbox.path().to_path_buf(),
+        (None, Some(cwd)) => test_dir.join(cwd),
+        (None, None) => test_dir.to_path_buf(),
+    };
+    if !dir.is_dir() {
+        return TestResult::failure(
+            test,
+            format!("Working directory {:?} (cwd) is not a directory", dir),
+        );
+    }
+    let workspace = workspace.insert(Workspace {
+        dir: dir.clone(),
+        sandbox,
+    });
+    // Removed when the test is done; a sandbox is fresh, so only there may they replace files
+    let _files = match TestFiles::write(&dir, &test.files, header.sandbox) {
+        Ok(files) => files,
+        Err(e) => {
+            return TestResult::failure(test, format!("Failed to write the Files section: {}", e));
+        }
+    };
+    // Held while the runner and setup steps run, but not while the output is compared. Taken
+    // before the timeout starts, so waiting for a slot does not count against it.
+    let slot = opts.jobs.as_deref().map(JobSlots::acquire);
+    let timeout = header.timeout.or(opts.timeout);
+    let timeout_at = timeout.map(|timeout| Instant::now() + timeout);
+    let child = ChildConfig {
+        dir: &dir,
+        env,
+        clean_env,
+        network: header.network.unwrap_or(!opts.no_network),
+        deadline: match (opts.deadline, timeout_at) {
+            (Some(run), Some(test)) => Some(run.min(test)),
+            (run, test) => run.or(test),
+        },
+        cancelled: opts.fail_fast.then_some(opts.cancelled.as_ref()),
+        combined_output: header.capture == Capture::Combined,
+        pty: header.pty,
+        shell: runner_shell(test, opts),
+    };
+    let setup_cmds: Vec<&str> = setup.iter().map(|(cmd, _)| cmd.as_str()).collect();
+    let shell_cmd = match child.shell {
+        // The reproduction command already runs in the default shell
+        Some(shell) if child.shell != DEFAULT_SHELL => match shell_invocation(shell, runner_cmd) {
+            Ok((_, args)) => shell_words::join(
+                [shell.name()]
+                    .into_iter()
+                    .chain(args.iter().map(String::as_str)),
+            ),
+            Err(e) => return TestResult::failure(test, e),
+        },
+        _ => runner_cmd.clone(),
+    };
+    let repro_cmd = if child.combined_output {
+        format!("{} 2>&1", shell_cmd)
+    } else {
+        shell_cmd
+    };
+    let repro_dir = if workspace.sandbox.is_some() {
+        ReproDir::Sandbox(fixtures.as_deref())
+    } else {
+        ReproDir::Path(child.dir)
+    };
+    // The input file is gone by the time the command is pasted, so it writes it again
+    let mut repro_files = test.files.clone();
+    let mut repro_stdin = test.repro_stdin();
+    if let Some(file) = &input_file {
+        repro_files.push((
+            file.path().to_string_lossy().into_owned(),
+            String::from_utf8_lossy(test.stdin()).into_owned(),
+        ));
+        repro_stdin.clear();
+    }
+    let repro = repro_command(
+        repro_dir,
+        &repro_files,
+        &child.env,
+        child.clean_env,
+        &setup_cmds,
+        &repro_cmd,
+        &repro_stdin,
+    );
+
+    // Setup steps get no input and only have their exit code checked
+    for (i, (cmd, exit_code)) in setup.iter().enumerate() {
+        let failure = |error: String| TestResult {
+            repro: Some(repro.clone()),
+            seed: Some(seed),
+            ..TestResult::failure(test, error)
+        };
+        match run_command(cmd, &[], &child) {
+            Ok(ChildOutcome::Exited(output, _)) if output.status.code() != *exit_code => {
+                return TestResult {
+                    exit_code: output.status.code(),
+                    stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                    stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                    ..failure(format!(
+                        "Step {} ({:?}) exited with {}, expected {}",
+                        i + 1,
+                        cmd,
+                        describe_exit(output.status.code()),
+                        describe_exit(*exit_code)
+                    ))
+                };
+            }
+            Ok(ChildOutcome::Exited(..)) => {}
+            Ok(ChildOutcome::Killed(output)) => {
+                return killed_result(test, opts, timeout, output, repro, seed);
+            }
+            Err(e) => return failure(format!("Step {}: {}", i + 1, e)),
+        }
+    }
+
+    let outcome = match (session, &test.dialog) {
+        _ if header.runner_mode == RunnerMode::Worker => {
+            opts.workers
+                .exchange(runner_cmd, &header.name, stdin, &child)
+        }
+        (Some(session), _) => session.exchange(runner_cmd, stdin, &child),
+        (None, Some(dialog)) => run_dialog(runner_cmd, dialog, &child),
+        (None, None) => run_command(runner_cmd, stdin, &child),
+    };
+    drop(slot);
+    let (output, peak_memory) = match outcome {
+        Ok(ChildOutcome::Exited(output, peak_memory)) => (output, peak_memory),
+        Ok(ChildOutcome::Killed(output)) => {
+            return killed_result(test, opts, timeout, output, repro, seed);
+        }
+        Err(e) => {
+            return TestResult {
+                repro: Some(repro),
+                seed: Some(seed),
+                ..TestResult::failure(test, e)
+            };
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Only the compared output is normalized; results keep what the runner printed
+    // Under `line_endings: auto` both sides are compared with `\n` line endings
+    let auto_newlines = header.line_endings == LineEndings::Auto;
+    let strip = header.strip_ansi.unwrap_or(opts.strip_ansi);
+    let compared = |s: &str| {
+        let s = if strip { strip_ansi(s) } else { s.into() };
+        let s = text.apply(&normalizer.apply(&s));
+        if auto_newlines {
+            normalize_newlines(&s)
+        } else {
+            s
+        }
+    };
+    let expected_text = |s: &str| {
+        let s = text.apply(s.trim());
+        if auto_newlines {
+            normalize_newlines(&s)
+        } else {
+            s
+        }
+    };
+    let (compared_stdout, compared_stderr) = (compared(&stdout), compared(&stderr));
+
+    // Failure-output tests assert on everything the runner printed, errors included,
+    // unless stderr has its own Expected Stderr block
+    let combined = test.header.expect_failure && test.expected_stderr.is_none();
+    let mut actual = if combined {
+        [compared_stdout.trim(), compared_stderr.trim()]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        compared_stdout.trim().to_owned()
+    };
+    let mut expected = expected_text(&test.expected_output).trim().to_owned();
+
+    let comparison = if test.is_shape_only() {
+        Comparison::of(true)
+    } else {
+        let input = CompareInput {
+            test,
+            expected: &expected,
+            actual: &actual,
+            stdout: &output.stdout,
+            ignore_paths: &ignore_paths,
+        };
+        match opts
+            .comparators
+            .select(test)
+            .and_then(|comparator| comparator.compare(&input))
+        {
+            Ok(comparison) => comparison,
+            Err(e) => {
+                return TestResult {
+                    repro: Some(repro),
+                    seed: Some(seed),
+                    ..TestResult::failure(test, e)
+                };
+            }
+        }
+    };
+    let matched = comparison.matched;
+    let stderr_diff = test
+        .expected_stderr
+        .as_ref()
+        .map(|e| {
+            (
+                compared_stderr.trim().to_owned(),
+                expected_text(e).trim().to_owned(),
+            )
+        })
+        .filter(|(actual, expected)| actual != expected);
+
+    let mut warnings = vec![];
+    if test.has_empty_expected() {
+        warnings.push("Expected Output block is empty; test passes vacuously".to_string());
+    }
+    let mut error = if test.header.expect_failure && output.status.success() {
+        Some("Expected runner to exit with a non-zero status, but it succeeded".to_string())
+    } else if final_exit_code.is_some() && output.status.code() != *final_exit_code {
+        Some(format!(
+            "Runner exited with {}, expected {}",
+            describe_exit(output.status.code()),
+            describe_exit(*final_exit_code)
+        ))
+    } else if !matched {
+        Some("Output did not match expected".to_string())
+    } else if stderr_diff.is_some() {
+        Some("Stderr did not match Expected Stderr".to_string())
+    } else {
+        test.header.check_shape(&stdout).err()
+    };
+    if let Some(limit_mb) = test.header.max_memory_mb {
+        match peak_memory {
+            Some(peak) if peak > limit_mb * 1024 * 1024 => {
+                error = Some(format!(
+                    "Peak memory {} exceeded max_memory_mb of {} MB",
+                    format_bytes(peak),
+                    limit_mb
+                ));
+            }
+            Some(_) => {}
+            None => warnings.push(
+                "max_memory_mb is set but peak memory is not available on this platform"
+                    .to_string(),
+            ),
+        }
+    }
+    // Files the runner must have produced, checked once the output itself is fine
+    let mut file_diff = None;
+    if error.is_none() {
+        for (path, content) in &test.expected_files {
+            let bytes = match fs::read(child.dir.join(path)) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error = Some(format!("Expected file {:?} could not be read: {}", path, e));
+                    break;
+                }
+            };
+            // Normalized and compared like stdout
+            let actual_file = compared(&String::from_utf8_lossy(&bytes)).trim().to_owned();
+            let expected_file = expected_text(content);
+            let input = CompareInput {
+                test,
+                expected: &expected_file,
+                actual: &actual_file,
+                stdout: &bytes,
+                ignore_paths: &ignore_paths,
+            };
+            match opts
+                .comparators
+                .select_file(test, path)
+                .and_then(|comparator| comparator.compare(&input))
+            {
+                Ok(comparison) if comparison.matched => {}
+                Ok(comparison) => {
+                    error = Some(format!("Expected file {:?} did not match", path));
+                    file_diff = Some(comparison.shown.unwrap_or((actual_file, expected_file)));
+                    break;
+                }
+                Err(e) => {
+                    error = Some(format!("Expected file {:?}: {}", path, e));
+                    break;
+                }
+            }
+        }
+    }
+    let passed = error.is_none();
+
+    if let Some((a, e)) = comparison.shown {
+        actual = a;
+        expected = e;
+    }
+    // With matching stdout, a failure's diff is best spent on stderr
+    if matched && let Some((a, e)) = stderr_diff {
+        actual = a;
+        expected = e;
+    }
+    if let Some((a, e)) = file_diff {
+        actual = a;
+        expected = e;
+    }
+
+    TestResult {
+        name: test.header.name.clone(),
+        file: test.file.clone(),
+        tags: test.header.tags.clone(),
+        passed,
+        skipped: None,
+        duration: Duration::ZERO,
+        slow: false,
+        too_slow: false,
+        flaky: false,
+        peak_memory,
+        exit_code: output.status.code(),
+        seed: Some(seed),
+        actual: if matched || test.header.expect_failure || test.expected_stderr.is_some() {
+            actual.clone()
+        } else if !stderr.trim().is_empty() {
+            format!("{}\n[stderr:{}]", actual, stderr.trim())
+        } else {
+            actual.clone()
+        },
+        expected,
+        stdout: stdout.into_owned(),
+        stderr: stderr.into_owned(),
+        error,
+        warnings,
+        repro: if passed { None } else { Some(repro) },
+        artifacts: None,
+        attempts: vec![],
+        diverged: vec![],
+    }
+}
+
+/// Result of a test whose runner was killed: skipped if the run was cancelled, otherwise a
+/// failure for the run deadline or the test's timeout, with the output printed until then
+fn killed_result(
+    test: &MarcoTestCase,
+    opts: &RunOptions,
+    timeout: Option<Duration>,
+    output: Output,
+    repro: String,
+    seed: u64,
+) -> TestResult {
+    if opts.is_cancelled() {
+        return TestResult::skipped(test, CANCELLED_REASON);
+    }
+    let error = match timeout {
+        Some(timeout) if !opts.deadline_exceeded() => {
+            format!("Killed: timed out after {:?}", timeout)
+        }
+        _ => "Killed: run deadline exceeded".to_string(),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    TestResult {
+        actual: stdout.trim().to_string(),
+        stdout,
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        repro: Some(repro),
+        seed: Some(seed),
+        ..TestResult::failure(test, error)
+    }
+}
+
+/// Writes a failed test's stdout, stderr and diff into a per-test directory under `root`,
+/// along with its sandbox's contents or else the expected files the runner produced,
+/// returning that directory. Tests of different files may have the same name, so each file
+/// gets a directory of its own.
+fn collect_artifacts(
+    root: &Path,
+    test: &MarcoTestCase,
+    result: &TestResult,
+    workspace: Option<&Workspace>,
+    algorithm: Algorithm,
+) -> io::Result<PathBuf> {
+    let file = test.file.strip_prefix(".").unwrap_or(&test.file);
+    let target = root
+        .join(sanitize_file_name(&file.to_string_lossy()))
+        .join(sanitize_file_name(&test.header.name));
+    // Left over from an earlier run
+    if target.exists() {
+        fs::remove_dir_all(&target)?;
+    }
+    fs::create_dir_all(&target)?;
+    fs::write(target.join("stdout.txt"), &result.stdout)?;
+    fs::write(target.join("stderr.txt"), &result.stderr)?;
+    if result.actual.trim() != result.expected.trim() {
+        fs::write(
+            target.join("diff.txt"),
+            unified_diff(&result.actual, &result.expected, algorithm),
+        )?;
+    }
+    let Some(workspace) = workspace else {
+        return Ok(target);
+    };
+    if let Some(sandbox) = &workspace.sandbox {
+        let dest = target.join("sandbox");
+        fs::create_dir_all(&dest)?;
+        copy_dir(sandbox.path(), &dest)?;
+        return Ok(target);
+    }
+    for (path, _) in &test.expected_files {
+        // The parser rejects paths leaving the working directory, which would also leave `target`
+        if !is_relative_inside(path) {
+            return Err(io::Error::other(format!(
+                "expected file path {:?} leaves the working directory",
+                path
+            )));
+        }
+        let source = workspace.dir.join(path);
+        if !source.is_file() {
+            continue;
+        }
+        let dest = target.join(path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&source, &dest)?;
+    }
+    Ok(target)
+}
+
+/// How the runner's child processes are spawned
+pub struct ChildConfig<'a> {
+    /// Working directory: the directory containing the test file, its `cwd` or its sandbox
+    pub dir: &'a Path,
+    /// Extra environment variables for the child
+    pub env: Vec<(String, String)>,
+    /// Whether the child only gets `env` instead of inheriting marco's environment
+    pub clean_env: bool,
+    /// Whether the child may access the network
+    pub network: bool,
+    /// Point at which the child is killed: the run deadline or the test's timeout, whichever
+    /// comes first
+    pub deadline: Option<Instant>,
+    /// Flag on which the child is killed, set when `--fail-fast` stops the run
+    pub cancelled: Option<&'a AtomicBool>,
+    /// Whether stderr is written into the stdout pipe, interleaved in the order it was printed
+    pub combined_output: bool,
+    /// Whether the child runs under a pseudo-terminal, which its stdout and stderr both go to
+    pub pty: bool,
+    /// Shell that runs the command, or `None` to start it directly
+    pub shell: Option<Shell>,
+}
+
+impl ChildConfig<'_> {
+    /// Whether the deadline has passed or the run was cancelled
+    pub fn should_abort(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+            || self.cancelled.is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+}
+
+/// Spawns a runner command, feeds it `input` on stdin and waits for it to finish
+fn run_command(
+    runner_cmd: &str,
+    input: &[u8],
+    config: &ChildConfig,
+) -> Result<ChildOutcome, String> {
+    block_on(async {
+        let (child, monitor) = spawn_async(runner_cmd, config)?;
+        supervise(child, monitor, input, config).await
+    })?
+}
+
+/// What ends a pseudo-terminal's input, which closing its stdin does not: the first Ctrl-D
+/// submits an unfinished line, the second reads as end of file
+const TERMINAL_EOF: &[u8] = b"\x04\x04";
+
+/// Spawns a runner command with piped stdio and starts monitoring it
+pub fn spawn_child(
+    runner_cmd: &str,
+    config: &ChildConfig,
+) -> Result<(Child, ChildMonitor), String> {
+    let (mut command, runner) = PreparedRunner::new(runner_cmd, config)?;
+    let spawned = command.spawn();
+    // Our copies of the write ends must be closed for the reader to see the end of the output
+    drop(command);
+    let mut child = spawned.map_err(|e| runner.spawn_error(e, config))?;
+    let (stdin, stdout) = runner.into_stdio()?;
+    if stdin.is_some() {
+        child.stdin = stdin;
+    }
+    if stdout.is_some() {
+        child.stdout = stdout;
+    }
+    let monitor = ChildMonitor::attach(&child);
+    Ok((child, monitor))
+}
+
+/// Like `spawn_child`, spawning the runner with tokio; must be called on its runtime
+pub fn spawn_async(
+    runner_cmd: &str,
+    config: &ChildConfig,
+) -> Result<(tokio::process::Child, ChildMonitor), String> {
+    let (command, runner) = PreparedRunner::new(runner_cmd, config)?;
+    let mut command = tokio::process::Command::from(command);
+    let spawned = command.spawn();
+    drop(command);
+    let mut child = spawned.map_err(|e| runner.spawn_error(e, config))?;
+    let (stdin, stdout) = runner.into_stdio()?;
+    let pipe_error = |e: io::Error| format!("Failed to set up the child's pipes: {}", e);
+    if let Some(stdin) = stdin {
+        child.stdin = Some(tokio::process::ChildStdin::from_std(stdin).map_err(pipe_error)?);
+    }
+    if let Some(stdout) = stdout {
+        child.stdout = Some(tokio::process::ChildStdout::from_std(stdout).map_err(pipe_error)?);
+    }
+    let monitor = ChildMonitor::attach_async(&child);
+    Ok((child, monitor))
+}
+
+/// What a runner's command is spawned from, with the pipes marco sets up itself for a combined
+/// output or a pseudo-terminal
+struct PreparedRunner {
+    prog: String,
+    args: Vec<String>,
+    /// Read end of the pipe that is both stdout and stderr
+    combined: Option<io::PipeReader>,
+    /// Controller side of the pseudo-terminal
+    #[cfg(unix)]
+    pty: Option<std::os::fd::OwnedFd>,
+}
+
+impl PreparedRunner {
+    /// The command that spawns the runner, and what is needed once it is spawned
+    fn new(runner_cmd: &str, config: &ChildConfig) -> Result<(Command, Self), String> {
+        let dir = config.dir;
+        let (prog, args) = match config.shell {
+            Some(shell) => shell_invocation(shell, runner_cmd)?,
+            None => match parse_shell_cmd(runner_cmd) {
+                Some(x) => (x.0, x.1),
+                None => return Err(format!("Malformed 'runner' command: {:?}", runner_cmd)),
+            },
+        };
+
+        let mut command = Command::new(&prog);
+        #[cfg(unix)]
+        let pty = if config.pty {
+            let controller = attach_pty(&mut command)
+                .map_err(|e| format!("Failed to open a pseudo-terminal: {}", e))?;
+            Some(controller)
+        } else {
+            command.stdin(Stdio::piped());
+            None
+        };
+        #[cfg(windows)]
+        if config.pty {
+            return Err("`pty: true` is not supported on Windows".to_string());
+        } else {
+            command.stdin(Stdio::piped());
+        }
+        let combined = if config.pty {
+            None
+        } else if config.combined_output {
+            let (reader, writer) =
+                io::pipe().map_err(|e| format!("Failed to create output pipe: {}", e))?;
+            let stderr = writer
+                .try_clone()
+                .map_err(|e| format!("Failed to create output pipe: {}", e))?;
+            command.stdout(writer).stderr(stderr);
+            Some(reader)
+        } else {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            None
+        };
+        if config.clean_env {
+            command.env_clear();
+        }
+        #[cfg(windows)]
+        if config.shell == Some(Shell::Cmd) {
+            // cmd does not undo the quoting of arguments; `/S` only strips the outer quotes
+            std::os::windows::process::CommandExt::raw_arg(
+                &mut command,
+                format!("/D /S /C \"{}\"", runner_cmd),
+            );
+        } else {
+            command.args(&args);
+        }
+        #[cfg(not(windows))]
+        command.args(&args);
+        command
+            .current_dir(dir)
+            .envs(config.env.iter().map(|(key, value)| (key, value)));
+        // Lead a new process group, so a timeout can kill everything the runner started; a
+        // pseudo-terminal's session already is one
+        #[cfg(unix)]
+        if !config.pty {
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        }
+        if !config.network {
+            disable_network(&mut command);
+        }
+        let runner = PreparedRunner {
+            prog,
+            args,
+            combined,
+            #[cfg(unix)]
+            pty,
+        };
+        Ok((command, runner))
+    }
+
+    fn spawn_error(&self, e: io::Error, config: &ChildConfig) -> String {
+        format!(
+            "Runner spawn error: {} (prog: {:?} args: {:?} dir: {:?}{})",
+            e,
+            self.prog,
+            self.args,
+            config.dir,
+            if config.network {
+                ""
+            } else {
+                ", network isolated"
+            }
+        )
+    }
+
+    /// The stdin and stdout of the spawned child that marco set up itself, replacing the ones
+    /// `spawn` returned
+    fn into_stdio(self) -> Result<(Option<ChildStdin>, Option<ChildStdout>), String> {
+        let mut stdio = (None, None);
+        if let Some(reader) = self.combined {
+            #[cfg(unix)]
+            let stdout = ChildStdout::from(std::os::fd::OwnedFd::from(reader));
+            #[cfg(windows)]
+            let stdout = ChildStdout::from(std::os::windows::io::OwnedHandle::from(reader));
+            stdio.1 = Some(stdout);
+        }
+        #[cfg(unix)]
+        if let Some(controller) = self.pty {
+            let input = controller
+                .try_clone()
+                .map_err(|e| format!("Failed to open a pseudo-terminal: {}", e))?;
+            stdio = (
+                Some(ChildStdin::from(input)),
+                Some(ChildStdout::from(controller)),
+            );
+        }
+        Ok(stdio)
+    }
+}
+
+/// Shell of runners whose test and options set none
+const DEFAULT_SHELL: Option<Shell> = if cfg!(windows) {
+    Some(Shell::Powershell)
+} else {
+    None
+};
+
+/// Shell that runs a test's runner and hooks, if any
+fn runner_shell(test: &MarcoTestCase, opts: &RunOptions) -> Option<Shell> {
+    if test.header.exec {
+        return None;
+    }
+    test.header.shell.or(opts.shell).or(DEFAULT_SHELL)
+}
+
+/// Values of the `{{...}}` variables of a test's runner commands: the test file, its
+/// directory and the test's name, plus `tmpdir`, `input_file` and `repo_root` when used
+fn template_vars(
+    test: &MarcoTestCase,
+    steps: &[(String, Option<i32>)],
+    tmpdir: Option<&Path>,
+    input_file: Option<&Path>,
+) -> Result<Vec<(&'static str, String)>, String> {
+    // The runner does not necessarily start in marco's working directory
+    let file = std::path::absolute(&test.file).unwrap_or_else(|_| test.file.clone());
+    let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut vars = vec![
+        ("file", file.to_string_lossy().into_owned()),
+        ("dir", dir.to_string_lossy().into_owned()),
+        ("name", test.header.name.clone()),
+    ];
+    if let Some(tmpdir) = tmpdir {
+        vars.push(("tmpdir", tmpdir.to_string_lossy().into_owned()));
+    }
+    if let Some(input_file) = input_file {
+        vars.push(("input_file", input_file.to_string_lossy().into_owned()));
+    }
+    if steps.iter().any(|(cmd, _)| cmd.contains("{{repo_root}}")) {
+        // `.git` is a file in worktrees and submodules
+        let root = dir
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .ok_or_else(|| {
+                format!(
+                    "The runner uses {{{{repo_root}}}}, but {:?} is not in a git repository",
+                    dir
+                )
+            })?;
+        vars.push(("repo_root", root.to_string_lossy().into_owned()));
+    }
+    Ok(vars)
+}
+
+/// Replaces each `{{var}}` of `vars` in `cmd` with its value, quoted for `shell` so it stays one
+/// argument. A word of the command without quotes is quoted whole, so `{{dir}}/data.txt` stays
+/// one path even with spaces in it; inside quotes, as in `sh -c "cat {{file}}"`, the quoted value
+/// is escaped for them. Other text in braces, like awk's `{print}`, is left as it is.
+fn expand_template(cmd: &str, vars: &[(&str, String)], shell: Option<Shell>) -> String {
+    let mut expanded = String::new();
+    for (word, spaces) in split_words(cmd, shell) {
+        if !word.contains("{{") {
+            expanded.push_str(word);
+        } else if !word.contains(|c| quote_chars(shell).contains(&c)) {
+            // One pass, so braces in a value are not taken for variables
+            let mut replaced = String::new();
+            let mut tail = word;
+            let mut found = false;
+            while let Some(start) = tail.find("{{") {
+                replaced.push_str(&tail[..start]);
+                tail = &tail[start..];
+                match template_var(tail, vars) {
+                    Some((len, value)) => {
+                        replaced.push_str(value);
+                        tail = &tail[len..];
+                        found = true;
+                    }
+                    None => {
+                        replaced.push('{');
+                        tail = &tail[1..];
+                    }
+                }
+            }
+            replaced.push_str(tail);
+            if found {
+                expanded.push_str(&quote_arg(&replaced, shell));
+            } else {
+                expanded.push_str(word);
+            }
+        } else {
+            let mut quote = None;
+            let mut escaped = false;
+            let mut tail = word;
+            while let Some(c) = tail.chars().next() {
+                if !escaped
+                    && c == '{'
+                    && let Some((len, value)) = template_var(tail, vars)
+                {
+                    expanded.push_str(&quote_in(value, quote, shell));
+                    tail = &tail[len..];
+                    continue;
+                }
+                (quote, escaped) = next_quote_state(c, quote, escaped, shell);
+                expanded.push(c);
+                tail = &tail[c.len_utf8()..];
+            }
+        }
+        expanded.push_str(spaces);
+    }
+    expanded
+}
+
+/// Characters that start and end quoted text for `shell`
+fn quote_chars(shell: Option<Shell>) -> &'static [char] {
+    match shell {
+        Some(Shell::Cmd) => &['"'],
+        _ => &['\'', '"'],
+    }
+}
+
+/// The quote `c` is in, and whether it is escaped, after `c` when the text before it left
+/// `quote` open and escaped `c` or not
+fn next_quote_state(
+    c: char,
+    quote: Option<char>,
+    escaped: bool,
+    shell: Option<Shell>,
+) -> (Option<char>, bool) {
+    let escape = match shell {
+        Some(Shell::Pwsh | Shell::Powershell) => Some('`'),
+        Some(Shell::Cmd) => None,
+        Some(Shell::Bash) | None => Some('\\'),
+    };
+    if escaped {
+        return (quote, false);
+    }
+    match quote {
+        Some(q) if c == q => (None, false),
+        // Nothing is escaped in single quotes
+        Some('\'') => (quote, false),
+        _ if Some(c) == escape => (quote, true),
+        None if quote_chars(shell).contains(&c) => (Some(c), false),
+        _ => (quote, false),
+    }
+}
+
+/// Splits a command into words and the whitespace after each, keeping quoted text in its word
+fn split_words(cmd: &str, shell: Option<Shell>) -> Vec<(&str, &str)> {
+    let mut words = vec![];
+    let (mut quote, mut escaped) = (None, false);
+    let mut start = 0;
+    let mut spaces = None;
+    for (i, c) in cmd.char_indices() {
+        let unquoted_space = quote.is_none() && !escaped && c.is_whitespace();
+        match spaces {
+            Some(from) if !unquoted_space => {
+                words.push((&cmd[start..from], &cmd[from..i]));
+                start = i;
+                spaces = None;
+            }
+            None if unquoted_space => spaces = Some(i),
+            _ => {}
+        }
+        (quote, escaped) = next_quote_state(c, quote, escaped, shell);
+    }
+    let from = spaces.unwrap_or(cmd.len());
+    words.push((&cmd[start..from], &cmd[from..]));
+    words
+}
+
+/// Length and value of the `{{var}}` of `vars` that `text` starts with
+fn template_var<'v>(text: &str, vars: &'v [(&str, String)]) -> Option<(usize, &'v str)> {
+    vars.iter().find_map(|(var, value)| {
+        let rest = text.strip_prefix("{{")?.strip_prefix(var)?;
+        rest.starts_with("}}")
+            .then(|| (var.len() + 4, value.as_str()))
+    })
+}
+
+/// Quotes a template value as one argument for `shell`, escaping that for the quotes it lands
+/// in, if any: a quoted command like `sh -c "cat {{file}}"` is run by another shell, which then
+/// gets the value as one argument too
+fn quote_in(value: &str, quote: Option<char>, shell: Option<Shell>) -> String {
+    let value = quote_arg(value, shell);
+    let escape = |escape: char, special: &[char]| {
+        let mut escaped = String::new();
+        for c in value.chars() {
+            if special.contains(&c) {
+                escaped.push(escape);
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    match (quote, shell) {
+        (None, _) => value,
+        (Some('\''), Some(Shell::Pwsh | Shell::Powershell)) => value.replace('\'', "''"),
+        // Ends the quotes, adds a quoted quote and reopens them
+        (Some('\''), _) => value.replace('\'', r#"'\''"#),
+        (Some(_), Some(Shell::Pwsh | Shell::Powershell)) => escape('`', &['`', '"', '$']),
+        (Some(_), Some(Shell::Cmd)) => value.replace('"', "\"\""),
+        (Some(_), _) => escape('\\', &['\\', '"', '$', '`']),
+    }
+}
+
+/// Appends the lines of an Args section to a runner command, each quoted as one argument
+fn append_args(runner_cmd: &str, args: &[String], shell: Option<Shell>) -> String {
+    let mut cmd = runner_cmd.to_string();
+    for arg in args {
+        cmd.push(' ');
+        cmd.push_str(&quote_arg(arg, shell));
+    }
+    cmd
+}
+
+/// Quotes `arg` as one argument for `shell`, or for splitting into words without one
+fn quote_arg(arg: &str, shell: Option<Shell>) -> String {
+    match shell {
+        Some(Shell::Pwsh | Shell::Powershell) => format!("'{}'", arg.replace('\'', "''")),
+        Some(Shell::Cmd) => format!("\"{}\"", arg.replace('"', "\"\"")),
+        Some(Shell::Bash) | None => shell_words::quote(arg).into_owned(),
+    }
+}
+
+/// Program and arguments that run `runner_cmd` with `shell`
+pub fn shell_invocation(shell: Shell, runner_cmd: &str) -> Result<(String, Vec<String>), String> {
+    let program = find_shell(shell).ok_or_else(|| {
+        format!(
+            "`shell: {}` is not installed (not found on PATH)",
+            shell.name()
+        )
+    })?;
+    let args: &[&str] = match shell {
+        Shell::Pwsh | Shell::Powershell => &["-NoProfile", "-Command"],
+        Shell::Cmd => &["/D", "/S", "/C"],
+        Shell::Bash => &["-c"],
+    };
+    let args = args
+        .iter()
+        .copied()
+        .chain([runner_cmd])
+        .map(str::to_string)
+        .collect();
+    Ok((program.to_string_lossy().into_owned(), args))
+}
+
+/// Directory of a test's file, which relative paths in its header are resolved against
+fn test_dir(test: &MarcoTestCase) -> &Path {
+    test.file
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Describes an exit code for error messages, e.g. `code 1` or `a signal`
+fn describe_exit(code: Option<i32>) -> String {
+    match code {
+        Some(code) => format!("code {}", code),
+        None => "a signal".to_string(),
+    }
+}
+
+/// How a runner's child process ended
+pub enum ChildOutcome {
+    /// It exited by itself, with its output and peak memory
+    Exited(Output, Option<u64>),
+    /// It was killed at its deadline or on cancellation, with what it printed until then
+    Killed(Output),
+}
+
+/// How long to keep reading the pipes of a killed child, in case a process that escaped
+/// the kill still holds them open
+const KILLED_OUTPUT_GRACE: Duration = Duration::from_millis(200);
+
+/// Drives the IO of children on a runtime shared by all tests; the calling rayon thread
+/// blocks on it, so rayon keeps bounding how many children run at once
+pub fn block_on<T>(future: impl Future<Output = T>) -> Result<T, String> {
+    static RUNTIME: OnceLock<Result<tokio::runtime::Runtime, String>> = OnceLock::new();
+    let runtime = RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Failed to start the IO runtime: {}", e))
+    });
+    match runtime {
+        Ok(runtime) => Ok(runtime.block_on(future)),
+        Err(e) => Err(e.clone()),
+    }
+}
+
+/// Writes `input` to the child's stdin, if it still has one, and collects its output and peak
+/// memory once it exits, killing it and everything it started if the deadline passes or the
+/// run is cancelled first
+pub async fn supervise(
+    mut child: tokio::process::Child,
+    monitor: ChildMonitor,
+    input: &[u8],
+    config: &ChildConfig<'_>,
+) -> Result<ChildOutcome, String> {
+    let stderr = tokio::spawn(read_pipe(child.stderr.take()));
+    supervise_with_stderr(child, monitor, stderr, input, config).await
+}
+
+/// Like `supervise`, for a child whose stderr is already being read by `stderr`
+pub async fn supervise_with_stderr(
+    mut child: tokio::process::Child,
+    mut monitor: ChildMonitor,
+    mut stderr: JoinHandle<Vec<u8>>,
+    input: &[u8],
+    config: &ChildConfig<'_>,
+) -> Result<ChildOutcome, String> {
+    // The pipes are read while the input is written, so neither side can block the other
+    let mut stdout = tokio::spawn(read_pipe(child.stdout.take()));
+    let mut write = Box::pin(write_input(child.stdin.take(), input, config.pty));
+    let mut writing = true;
+
+    let status = loop {
+        tokio::select! {
+            written = &mut write, if writing => {
+                writing = false;
+                // A child may exit without reading all of its input
+                if let Err(e) = written
+                    && e.kind() != io::ErrorKind::BrokenPipe
+                {
+                    monitor.kill_tree_async(&mut child);
+                    return Err(format!("Failed to write to child stdin: {}", e));
+                }
+            }
+            status = monitor.wait_async(&mut child) => {
+                break status.map_err(|e| format!("Failed waiting on child: {}", e))?;
+            }
+            _ = aborted(config) => {
+                monitor.kill_tree_async(&mut child);
+                let output = Output {
+                    status: monitor
+                        .wait_async(&mut child)
+                        .await
+                        .map_err(|e| format!("Failed waiting on child: {}", e))?,
+                    stdout: killed_output(&mut stdout).await,
+                    stderr: killed_output(&mut stderr).await,
+                };
+                return Ok(ChildOutcome::Killed(output));
+            }
+        }
+    };
+    // Closes stdin if the child exited before reading all of its input, as a process it
+    // started may hold it open without reading it either
+    drop(write);
+
+    // Processes the child started may hold the pipes open after it exits; they get until the
+    // deadline too, and are then killed along with the rest of the process tree
+    let out = pipe_output(&mut stdout, config).await;
+    let err = pipe_output(&mut stderr, config).await;
+    if let (Some(out), Some(err)) = (&out, &err) {
+        let output = Output {
+            status,
+            stdout: out.clone(),
+            stderr: err.clone(),
+        };
+        return Ok(ChildOutcome::Exited(output, monitor.peak_memory()));
+    }
+    monitor.kill_tree_async(&mut child);
+    let output = Output {
+        status,
+        stdout: match out {
+            Some(out) => out,
+            None => killed_output(&mut stdout).await,
+        },
+        stderr: match err {
+            Some(err) => err,
+            None => killed_output(&mut stderr).await,
+        },
+    };
+    Ok(ChildOutcome::Killed(output))
+}
+
+/// Writes the child's input, then closes its stdin
+pub async fn write_input(
+    stdin: Option<tokio::process::ChildStdin>,
+    input: &[u8],
+    pty: bool,
+) -> io::Result<()> {
+    let Some(mut stdin) = stdin else {
+        return Ok(());
+    };
+    stdin.write_all(input).await?;
+    if pty {
+        stdin.write_all(TERMINAL_EOF).await?;
+    }
+    Ok(())
+}
+
+/// Resolves once the deadline passes or the run is cancelled
+pub async fn aborted(config: &ChildConfig<'_>) {
+    let deadline = async {
+        match config.deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    };
+    let cancelled = async {
+        match config.cancelled {
+            Some(cancelled) => {
+                while !cancelled.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = deadline => {}
+        _ = cancelled => {}
+    }
+}
+
+/// Reads a child pipe to the end, keeping what was read before an error such as a closed
+/// pseudo-terminal
+pub async fn read_pipe(pipe: Option<impl AsyncRead + Unpin>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mut pipe) = pipe {
+        let _ = pipe.read_to_end(&mut buf).await;
+    }
+    buf
+}
+
+/// Everything read from a pipe once it closes, or `None` if the run is aborted first
+async fn pipe_output(
+    reader: &mut JoinHandle<Vec<u8>>,
+    config: &ChildConfig<'_>,
+) -> Option<Vec<u8>> {
+    tokio::select! {
+        output = reader => Some(output.unwrap_or_default()),
+        _ = aborted(config) => None,
+    }
+}
+
+/// What a killed child's pipe yields within `KILLED_OUTPUT_GRACE`. The reader is stopped
+/// then, as a process that escaped the kill may hold the pipe open.
+pub async fn killed_output(reader: &mut JoinHandle<Vec<u8>>) -> Vec<u8> {
+    let output = tokio::time::timeout(KILLED_OUTPUT_GRACE, &mut *reader).await;
+    reader.abort();
+    output.ok().and_then(Result::ok).unwrap_or_default()
+}
@@ -0,0 +1,137 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tells apart the sandboxes and input files created by this process
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh temporary directory a test runs in with `sandbox: true`; it is removed with
+/// everything the runner left in it when the sandbox is dropped
+pub struct Sandbox {
+    path: PathBuf,
+}
+
+impl Sandbox {
+    /// Creates the directory and copies the contents of `fixtures` into it
+    pub fn new(fixtures: Option<&Path>) -> io::Result<Self> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("marco-sandbox-{}-{}", process::id(), id));
+        // Left behind by an earlier process with the same id that was killed
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        let sandbox = Sandbox { path };
+        if let Some(fixtures) = fixtures {
+            copy_dir(fixtures, &sandbox.path).map_err(|e| {
+                io::Error::new(e.kind(), format!("copying fixtures {:?}: {}", fixtures, e))
+            })?;
+        }
+        Ok(sandbox)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The Input block of a test with `input_as: file`, written to a temporary file that is
+/// removed when dropped
+pub struct InputFile {
+    path: PathBuf,
+}
+
+impl InputFile {
+    pub fn new(input: &[u8]) -> io::Result<Self> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("marco-input-{}-{}", process::id(), id));
+        fs::write(&path, input)?;
+        Ok(InputFile { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InputFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Copies the contents of `from` into the existing directory `to`, recursively
+pub fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// The files of a test's "Files" section, written into its working directory; they are
+/// removed, along with the directories created for them, when dropped
+#[derive(Default)]
+pub struct TestFiles {
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+}
+
+impl TestFiles {
+    /// Writes `files`, as (relative path, content), into `dir`. Unless `overwrite` is set, a
+    /// file that already exists is an error rather than being replaced and removed.
+    pub fn write(dir: &Path, files: &[(String, String)], overwrite: bool) -> io::Result<Self> {
+        let mut written = TestFiles::default();
+        for (path, content) in files {
+            let target = dir.join(path);
+            if !overwrite && target.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{:?} already exists", target),
+                ));
+            }
+            // Outermost first, so they are removed innermost first
+            let mut missing: Vec<_> = target
+                .ancestors()
+                .skip(1)
+                .take_while(|parent| !parent.exists())
+                .map(Path::to_path_buf)
+                .collect();
+            missing.reverse();
+            for parent in missing {
+                fs::create_dir(&parent)?;
+                written.dirs.push(parent);
+            }
+            fs::write(&target, content)?;
+            written.files.push(target);
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+        // Only succeeds for directories the runner left empty
+        for dir in self.dirs.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ExitStatus, Output};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dialog::find;
+use crate::memory::ChildMonitor;
+use crate::runner::{ChildConfig, ChildOutcome, spawn_child};
+
+/// How long a session runner gets to exit after its stdin is closed before it is killed
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// How long to wait for the delimiter when neither the test nor the run has a timeout
+const DELIMITER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// One long-lived runner process shared by the tests of a file with `session: true`. Each
+/// test's input is written to it in turn, and its output is what the runner prints until
+/// the `session_delimiter`.
+pub struct Session {
+    delimiter: String,
+    /// Whether the runner prints the delimiter once when it starts, before reading any input
+    prompt: bool,
+    process: Mutex<Option<SessionProcess>>,
+}
+
+struct SessionProcess {
+    child: Child,
+    monitor: ChildMonitor,
+    stdin: Option<ChildStdin>,
+    stdout: Receiver<Vec<u8>>,
+    stderr: Receiver<Vec<u8>>,
+    /// Output read past the last delimiter
+    pending: Vec<u8>,
+}
+
+/// What reading a session runner's output until the delimiter ended with
+enum Reply {
+    /// The output before the delimiter
+    Answer(Vec<u8>),
+    /// The runner was killed at the deadline or on cancellation
+    Killed(Output),
+}
+
+impl Session {
+    pub fn new(delimiter: impl Into<String>, prompt: bool) -> Self {
+        Session {
+            delimiter: delimiter.into(),
+            prompt,
+            process: Mutex::new(None),
+        }
+    }
+
+    /// Writes `input` to the session's runner, starting it with `runner_cmd` if it is not
+    /// running, and collects what it prints until the delimiter. A runner that misses its
+    /// deadline is killed, and the next test starts a new one.
+    pub fn exchange(
+        &self,
+        runner_cmd: &str,
+        input: &[u8],
+        config: &ChildConfig,
+    ) -> Result<ChildOutcome, String> {
+        let mut process = self.lock();
+        if process.is_none() {
+            *process = Some(SessionProcess::spawn(runner_cmd, config)?);
+            // The prompt is skipped before the first input is written, so it cannot be
+            // mistaken for the answer of a test that prints nothing
+            if self.prompt
+                && let Reply::Killed(output) = self.read_reply(&mut process, config)?
+            {
+                return Ok(ChildOutcome::Killed(output));
+            }
+        }
+        let running = process.as_mut().expect("session runner was started");
+        if let Some(stdin) = running.stdin.as_mut() {
+            stdin
+                .write_all(input)
+                .and_then(|_| stdin.flush())
+                .map_err(|e| format!("Failed to write to the session runner: {}", e))?;
+        }
+        Ok(match self.read_reply(&mut process, config)? {
+            Reply::Answer(stdout) => {
+                let running = process.as_mut().expect("session runner is running");
+                let output = Output {
+                    status: ExitStatus::default(),
+                    stdout,
+                    stderr: running.stderr.try_iter().flatten().collect(),
+                };
+                ChildOutcome::Exited(output, None)
+            }
+            Reply::Killed(output) => ChildOutcome::Killed(output),
+        })
+    }
+
+    /// Reads the runner's output until the delimiter. A runner that misses the deadline, or
+    /// `DELIMITER_TIMEOUT` without one, is killed, and the next test starts a new one.
+    fn read_reply(
+        &self,
+        process: &mut Option<SessionProcess>,
+        config: &ChildConfig,
+    ) -> Result<Reply, String> {
+        let fallback = Instant::now() + DELIMITER_TIMEOUT;
+        let deadline = config.deadline.unwrap_or(fallback);
+        let delimiter = self.delimiter.as_bytes();
+        let running = process.as_mut().expect("session runner is running");
+        loop {
+            if let Some(pos) = find(&running.pending, delimiter) {
+                let stdout = running.pending.drain(..pos + delimiter.len()).take(pos);
+                return Ok(Reply::Answer(stdout.collect()));
+            }
+            let mut wait = deadline.saturating_duration_since(Instant::now());
+            if config.cancelled.is_some() {
+                // Wake up regularly to notice cancellation
+                wait = wait.min(Duration::from_millis(50));
+            }
+            match running.stdout.recv_timeout(wait) {
+                Ok(chunk) => running.pending.extend(chunk),
+                Err(RecvTimeoutError::Timeout)
+                    if !config.should_abort() && Instant::now() < deadline => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    let mut killed = process.take().expect("session runner is running");
+                    killed.monitor.kill_tree(&mut killed.child);
+                    let status = killed
+                        .child
+                        .wait()
+                        .map_err(|e| format!("Failed waiting on child: {}", e))?;
+                    let stdout = std::mem::take(&mut killed.pending);
+                    let stderr: Vec<u8> = killed.stderr.try_iter().flatten().collect();
+                    if config.deadline.is_none() && !config.should_abort() {
+                        return Err(format!(
+                            "Session runner printed no session_delimiter {:?} within {:?}; output so far:\n{}{}",
+                            self.delimiter,
+                            DELIMITER_TIMEOUT,
+                            String::from_utf8_lossy(&stdout),
+                            String::from_utf8_lossy(&stderr)
+                        ));
+                    }
+                    return Ok(Reply::Killed(Output {
+                        status,
+                        stdout,
+                        stderr,
+                    }));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    let mut exited = process.take().expect("session runner is running");
+                    let status = exited
+                        .child
+                        .wait()
+                        .map_err(|e| format!("Failed waiting on child: {}", e))?;
+                    let stderr: Vec<u8> = exited.stderr.try_iter().flatten().collect();
+                    return Err(format!(
+                        "Session runner exited ({}) before printing the session_delimiter {:?}; output so far:\n{}{}",
+                        status,
+                        self.delimiter,
+                        String::from_utf8_lossy(&exited.pending),
+                        String::from_utf8_lossy(&stderr)
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Closes the runner's stdin and waits briefly for it to exit, killing it otherwise
+    pub fn finish(&self) {
+        let Some(mut process) = self.lock().take() else {
+            return;
+        };
+        drop(process.stdin.take());
+        let until = Instant::now() + EXIT_GRACE;
+        while Instant::now() < until {
+            if let Ok(Some(_)) = process.monitor.try_wait(&mut process.child) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        process.monitor.kill_tree(&mut process.child);
+        let _ = process.child.wait();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<SessionProcess>> {
+        self.process
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl SessionProcess {
+    fn spawn(runner_cmd: &str, config: &ChildConfig) -> Result<Self, String> {
+        let (mut child, monitor) = spawn_child(runner_cmd, config)?;
+        Ok(SessionProcess {
+            stdin: child.stdin.take(),
+            stdout: forward(child.stdout.take()),
+            stderr: forward(child.stderr.take()),
+            child,
+            monitor,
+            pending: vec![],
+        })
+    }
+}
+
+/// Forwards a pipe in chunks as they arrive, so output can be taken before the child exits
+fn forward(pipe: Option<impl Read + Send + 'static>) -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let Some(mut pipe) = pipe else { return };
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = pipe.read(&mut buf) {
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
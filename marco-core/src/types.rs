@@ -0,0 +1,642 @@
+use serde::{Deserialize, Serialize};
+
+use crate::dialog::DialogStep;
+use crate::faketime::FakeTimeConfig;
+use crate::normalize::{NormalizeRule, TextOptions};
+use crate::util::{deserialize_duration, deserialize_secs, normalize_newlines, serialize_secs};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A single runner command, optionally varying by platform
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum CommandConfig {
+    Simple(String),
+    Platform {
+        windows: Option<String>,
+        unix: Option<String>,
+        default: Option<String>,
+    },
+}
+
+impl CommandConfig {
+    pub fn for_current_platform(&self) -> &str {
+        match self {
+            CommandConfig::Simple(cmd) => cmd,
+            #[allow(unused_variables)]
+            CommandConfig::Platform {
+                windows,
+                unix,
+                default,
+            } => {
+                #[cfg(target_os = "windows")]
+                {
+                    windows.as_deref().or(default.as_deref()).unwrap_or("echo")
+                }
+                #[cfg(not(target_os = "windows"))]
+                {
+                    unix.as_deref().or(default.as_deref()).unwrap_or("echo")
+                }
+            }
+        }
+    }
+
+    /// Applies `f` to the command of every platform
+    pub fn map(&self, f: &impl Fn(&str) -> String) -> Self {
+        match self {
+            CommandConfig::Simple(cmd) => CommandConfig::Simple(f(cmd)),
+            CommandConfig::Platform {
+                windows,
+                unix,
+                default,
+            } => CommandConfig::Platform {
+                windows: windows.as_deref().map(f),
+                unix: unix.as_deref().map(f),
+                default: default.as_deref().map(f),
+            },
+        }
+    }
+}
+
+/// The `runner` header: a single command or a sequence of steps
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RunnerConfig {
+    Command(CommandConfig),
+    Steps(Vec<RunnerStep>),
+}
+
+/// One step of a multi-step runner. Only the final step's output is compared.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RunnerStep {
+    // Must come first: a `{ run, exit_code }` map would otherwise match `CommandConfig::Platform`
+    Checked {
+        run: CommandConfig,
+        exit_code: Option<i32>,
+    },
+    Command(CommandConfig),
+}
+
+impl RunnerConfig {
+    /// The commands to run in order for this platform, with the exit code each must return.
+    /// Setup steps must exit with 0 unless they specify otherwise.
+    pub fn steps(&self) -> Vec<(&str, Option<i32>)> {
+        match self {
+            RunnerConfig::Command(cmd) => vec![(cmd.for_current_platform(), None)],
+            RunnerConfig::Steps(steps) => {
+                let last = steps.len().saturating_sub(1);
+                steps
+                    .iter()
+                    .enumerate()
+                    .map(|(i, step)| {
+                        let (cmd, exit_code) = match step {
+                            RunnerStep::Checked { run, exit_code } => (run, *exit_code),
+                            RunnerStep::Command(cmd) => (cmd, None),
+                        };
+                        let default = (i < last).then_some(0);
+                        (cmd.for_current_platform(), exit_code.or(default))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Applies `f` to every command of every step
+    pub fn map(&self, f: &impl Fn(&str) -> String) -> Self {
+        match self {
+            RunnerConfig::Command(cmd) => RunnerConfig::Command(cmd.map(f)),
+            RunnerConfig::Steps(steps) => RunnerConfig::Steps(
+                steps
+                    .iter()
+                    .map(|step| match step {
+                        RunnerStep::Checked { run, exit_code } => RunnerStep::Checked {
+                            run: run.map(f),
+                            exit_code: *exit_code,
+                        },
+                        RunnerStep::Command(cmd) => RunnerStep::Command(cmd.map(f)),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[allow(unused)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestHeader {
+    pub name: String,
+    /// Who wrote or looks after the tests; informational only
+    pub author: Option<String>,
+    pub runner: Option<RunnerConfig>,
+    /// Shell that runs the runner command, overriding `--shell`
+    pub shell: Option<Shell>,
+    /// Start the runner command without any shell, split into words the same way on every
+    /// platform
+    #[serde(default)]
+    pub exec: bool,
+    /// Named runner variants; every test runs once per variant, with the name appended
+    pub runners: Option<BTreeMap<String, RunnerConfig>>,
+    /// Values per placeholder; every test runs once per combination, with `{key}` substituted
+    /// into the runner, Input and Expected Output
+    pub matrix: Option<BTreeMap<String, Vec<MatrixValue>>>,
+    pub max_memory_mb: Option<u64>,
+    /// Run the runner under a pseudo-terminal, so it sees a terminal on stdin, stdout and
+    /// stderr; both outputs are then compared together
+    #[serde(default)]
+    pub pty: bool,
+    /// Treat further Input/Expected Output pairs under a test's heading as steps of one
+    /// dialog with the same runner process, instead of as tests of their own
+    #[serde(default)]
+    pub interactive: bool,
+    /// Pass only if the runner exits non-zero and its combined stdout/stderr matches
+    #[serde(default)]
+    pub expect_failure: bool,
+    /// Only run on these platforms (OS, family or arch, e.g. `linux`, `unix`, `aarch64`)
+    #[serde(default)]
+    pub only_on: Vec<String>,
+    /// Skip on these platforms (OS, family or arch)
+    #[serde(default)]
+    pub skip_on: Vec<String>,
+    /// Tools that must be on PATH, optionally with a version constraint (e.g. `python3>=3.10`)
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Allow (`true`) or forbid (`false`) network access, overriding `--no-network`
+    pub network: Option<bool>,
+    /// Pin the child's clock and time-related env vars to a fixed time
+    pub fake_time: Option<FakeTimeConfig>,
+    /// Environment variables for the runner, overriding `--env`
+    #[serde(default)]
+    pub env: BTreeMap<String, MatrixValue>,
+    /// Working directory of the runner, relative to the test file, instead of the test file's
+    /// directory
+    pub cwd: Option<PathBuf>,
+    /// Run all of the file's tests against one runner process, writing their inputs to it in
+    /// turn
+    #[serde(default)]
+    pub session: bool,
+    /// Text the session runner prints after each test's output, e.g. a REPL's prompt
+    pub session_delimiter: Option<String>,
+    /// The session runner prints the delimiter once when it starts, e.g. a REPL's first
+    /// prompt, which is skipped before the first input is written
+    #[serde(default)]
+    pub session_prompt: bool,
+    /// `worker` sends the input to a long-lived runner shared with other tests, as a JSON
+    /// line, instead of starting a process for the test
+    #[serde(default)]
+    pub runner_mode: RunnerMode,
+    /// `file` writes the Input block to a temporary file whose path replaces `{{input_file}}`
+    /// in the runner command, instead of writing it to stdin
+    #[serde(default)]
+    pub input_as: InputAs,
+    /// Run the test in a fresh temporary directory, removed afterwards, so tests writing files
+    /// cannot clobber each other
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Directory, relative to the test file, whose contents are copied into the sandbox
+    pub fixtures: Option<PathBuf>,
+    /// Dotenv file, relative to the test file, loaded before `env` and overriding `--env`
+    pub env_file: Option<PathBuf>,
+    /// Start the runner with only `pass_env`, `env` and marco's own variables in its
+    /// environment, overriding `--clean-env`
+    pub clean_env: Option<bool>,
+    /// Variables of marco's environment the runner still gets with `clean_env`, besides `--pass-env`
+    #[serde(default)]
+    pub pass_env: Vec<String>,
+    /// Replace `${VAR}` in the blocks the test sends or compares with the value of `VAR` from
+    /// `env` or marco's environment; `\${` stays a literal `${`
+    #[serde(default)]
+    pub interpolate: bool,
+    /// Command run once in the file's directory before the first of its tests starts
+    pub before_all: Option<CommandConfig>,
+    /// Command run once after the last of the file's tests has finished, if any of them ran
+    pub after_all: Option<CommandConfig>,
+    /// Tests sharing a serial group never run concurrently
+    pub serial: Option<String>,
+    /// Maximum number of lines the runner may print to stdout
+    pub max_lines: Option<usize>,
+    /// Minimum number of lines the runner must print to stdout
+    pub min_lines: Option<usize>,
+    /// Maximum number of bytes the runner may print to stdout
+    pub max_bytes: Option<usize>,
+    /// Fail the test as too slow if it takes longer than this (e.g. `200ms`)
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub max_duration: Option<Duration>,
+    /// Kill the runner and every process it started after this long, failing the test
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// Rerun the test up to this many times while it fails, overriding `--retries`
+    pub retries: Option<u32>,
+    /// `combined` compares stdout and stderr merged in the order they were written
+    #[serde(default)]
+    pub capture: Capture,
+    /// How the output is compared with the Expected Output block
+    #[serde(default)]
+    pub compare: Compare,
+    /// Largest absolute difference `compare: numeric` accepts between two numbers
+    pub tolerance: Option<f64>,
+    /// Largest difference `compare: numeric` accepts, relative to the larger of two numbers
+    pub relative_tolerance: Option<f64>,
+    /// JSONPaths (`$.items[*].id`) or JSON Pointers of volatile fields left out of JSON comparisons
+    #[serde(default)]
+    pub ignore_paths: Vec<String>,
+    /// Remove terminal escape sequences (colors, cursor movement) from the output before it
+    /// is compared, overriding `--strip-ansi`
+    pub strip_ansi: Option<bool>,
+    /// `lf`, `crlf`, `preserve` or `auto` (the default)
+    #[serde(default)]
+    pub line_endings: LineEndings,
+    /// Regex replacements applied to the output before it is compared
+    #[serde(default)]
+    pub normalize: Vec<NormalizeRule>,
+    /// `ignore_case`, `ignore_trailing_whitespace`, `squash_whitespace` and `ignore_blank_lines`,
+    /// overriding the config files
+    #[serde(flatten)]
+    pub text: TextOptions,
+    /// Regexes, or `env:NAME` for the value of a variable, redacted from output and reports
+    #[serde(default)]
+    pub mask: Vec<String>,
+    /// Labels for slicing the suite with `--tag`/`--skip-tag`, e.g. `fast` or `network`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Scheduling order of the tests within their file; lower is dispatched first (default 0)
+    #[serde(default, alias = "priority")]
+    pub order: i64,
+}
+
+/// Shell a runner command is run with. Without one, runners are started directly, except on
+/// Windows, where `powershell` runs them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// PowerShell 7 and later
+    Pwsh,
+    /// Windows PowerShell 5
+    Powershell,
+    Cmd,
+    /// Git Bash or WSL on Windows
+    Bash,
+}
+
+impl Shell {
+    pub fn name(self) -> &'static str {
+        match self {
+            Shell::Pwsh => "pwsh",
+            Shell::Powershell => "powershell",
+            Shell::Cmd => "cmd",
+            Shell::Bash => "bash",
+        }
+    }
+}
+
+/// How a test's runner process is started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunnerMode {
+    /// A process of its own, given the input on stdin
+    #[default]
+    Process,
+    /// A worker process shared with other tests, given the input as a JSON request
+    Worker,
+}
+
+/// How a test's Input block is given to the runner
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputAs {
+    #[default]
+    Stdin,
+    /// A temporary file, removed once the test is done
+    File,
+}
+
+/// Which of the runner's output streams the Expected Output block is compared with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capture {
+    /// Stdout only; stderr can have an Expected Stderr block of its own
+    #[default]
+    Separate,
+    /// Stdout and stderr through one shared pipe, interleaved as in a terminal
+    Combined,
+}
+
+/// How the output is compared with the Expected Output block
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compare {
+    /// Equal text, or equal JSON values if both sides are JSON
+    #[default]
+    Exact,
+    /// Equal JSON values, regardless of key order and formatting
+    Json,
+    /// Equal YAML documents, like a ```` ```yaml ```` Expected Output block
+    Yaml,
+    /// Equal TOML documents, regardless of key order and formatting
+    Toml,
+    /// Equal text between numbers, and numbers within `tolerance`/`relative_tolerance`
+    Numeric,
+    /// The same lines in any order, e.g. for parallel logs or directory listings
+    UnorderedLines,
+    /// A comparator registered under this name by code embedding marco
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl Compare {
+    /// Name of the comparator this selects (see `compare::Comparators`)
+    pub fn name(&self) -> &str {
+        match self {
+            Compare::Exact => "exact",
+            Compare::Json => "json",
+            Compare::Yaml => "yaml",
+            Compare::Toml => "toml",
+            Compare::Numeric => "numeric",
+            Compare::UnorderedLines => "unordered-lines",
+            Compare::Custom(name) => name,
+        }
+    }
+}
+
+/// Line endings of the Input and Expected blocks, and whether the output's must match them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    /// `\n`, compared exactly
+    Lf,
+    /// `\r\n`, compared exactly
+    Crlf,
+    /// The test file's own line endings, compared exactly
+    Preserve,
+    /// The platform's line endings (`\r\n` on Windows, `\n` elsewhere); the output may use either
+    #[default]
+    Auto,
+}
+
+impl LineEndings {
+    /// Rewrites a block of the test file, which uses `file_newline`, to these line endings
+    pub fn apply(self, block: &str, file_newline: &str) -> String {
+        let newline = match self {
+            LineEndings::Lf => "\n",
+            LineEndings::Crlf => "\r\n",
+            LineEndings::Preserve => file_newline,
+            LineEndings::Auto if cfg!(windows) => "\r\n",
+            LineEndings::Auto => "\n",
+        };
+        normalize_newlines(block).replace('\n', newline)
+    }
+}
+
+/// One value of a `matrix` or `env` entry; YAML numbers and booleans are used as written
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MatrixValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl std::fmt::Display for MatrixValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatrixValue::Bool(b) => write!(f, "{}", b),
+            MatrixValue::Int(n) => write!(f, "{}", n),
+            MatrixValue::Float(n) => write!(f, "{}", n),
+            MatrixValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl TestHeader {
+    /// Whether any of `max_lines`, `min_lines` or `max_bytes` is set
+    pub fn has_shape_assertions(&self) -> bool {
+        self.max_lines.is_some() || self.min_lines.is_some() || self.max_bytes.is_some()
+    }
+
+    /// Checks the raw stdout against the output shape assertions
+    pub fn check_shape(&self, stdout: &str) -> Result<(), String> {
+        let lines = stdout.lines().count();
+        if let Some(max) = self.max_lines
+            && lines > max
+        {
+            return Err(format!(
+                "Output has {} lines, more than max_lines of {}",
+                lines, max
+            ));
+        }
+        if let Some(min) = self.min_lines
+            && lines < min
+        {
+            return Err(format!(
+                "Output has {} lines, fewer than min_lines of {}",
+                lines, min
+            ));
+        }
+        if let Some(max) = self.max_bytes
+            && stdout.len() > max
+        {
+            return Err(format!(
+                "Output has {} bytes, more than max_bytes of {}",
+                stdout.len(),
+                max
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[allow(unused)]
+#[derive(Debug, Clone)]
+pub struct MarcoTestCase {
+    pub header: TestHeader,
+    pub file: PathBuf,
+    pub input_data: String,
+    /// Steps of a ```` ```dialog ```` Input block, run interactively instead of piping `input_data`
+    pub dialog: Option<Vec<DialogStep>>,
+    /// Language (or file extension) from the Input block's fence tag, e.g. `py`
+    pub input_lang: Option<String>,
+    /// Decoded bytes of a ```` ```hex ```` / ```` ```base64 ```` Input block, sent instead of `input_data`
+    pub raw_input: Option<Vec<u8>>,
+    pub expected_output: String,
+    /// Decoded bytes of a ```` ```hex ```` / ```` ```base64 ```` Expected Output block, compared
+    /// byte for byte with the raw stdout
+    pub expected_bytes: Option<Vec<u8>>,
+    /// SHA-256 digest from a ```` ```sha256 ```` Expected Output block, which the raw stdout must
+    /// hash to, for outputs too large to embed
+    pub expected_sha256: Option<String>,
+    /// Whether the Expected Output block is tagged ```` ```regex ````, making each of its lines
+    /// a regex the output line at the same position must match in full
+    pub expected_regex: bool,
+    /// Whether the Expected Output block is tagged ```` ```yaml ```` (or `yml`), making the output
+    /// compare as YAML documents instead of text
+    pub expected_yaml: bool,
+    /// Content of an "Expected Stderr" block; stderr is only compared if the test has one
+    pub expected_stderr: Option<String>,
+    pub block_start_line: usize,
+    /// Reason to skip the test, set during collection (e.g. a missing required tool)
+    pub skip: Option<String>,
+    /// Files from the test's "Files" section, written into its working directory before the
+    /// runner starts, as (relative path, content)
+    pub files: Vec<(String, String)>,
+    /// Lines of the test's "Args" section, appended to the runner command as arguments
+    pub args: Vec<String>,
+    /// Files the runner must create in its working directory, as (relative path, content)
+    pub expected_files: Vec<(String, String)>,
+    /// Index of the Expected Output block among the file's code blocks, if it can be rewritten
+    pub expected_block: Option<usize>,
+}
+
+impl MarcoTestCase {
+    /// The bytes written to the runner's stdin
+    pub fn stdin(&self) -> &[u8] {
+        self.raw_input
+            .as_deref()
+            .unwrap_or(self.input_data.as_bytes())
+    }
+
+    /// Stdin for the reproduction command; for dialogs, every `send:` line up front
+    pub fn repro_stdin(&self) -> Vec<u8> {
+        match &self.dialog {
+            Some(dialog) => dialog
+                .iter()
+                .filter_map(|step| match step {
+                    DialogStep::Send(line) => Some(format!("{}\n", line)),
+                    DialogStep::Expect { .. } => None,
+                })
+                .collect::<String>()
+                .into_bytes(),
+            None => self.stdin().to_vec(),
+        }
+    }
+
+    /// Whether the Expected Output block is empty or whitespace-only and nothing else is asserted
+    pub fn has_empty_expected(&self) -> bool {
+        self.expected_output.trim().is_empty() && !self.header.has_shape_assertions()
+    }
+
+    /// Whether only the output's shape is asserted: the Expected Output block is empty and
+    /// `max_lines`/`min_lines`/`max_bytes` are set, so the content is not compared
+    pub fn is_shape_only(&self) -> bool {
+        self.expected_output.trim().is_empty() && self.header.has_shape_assertions()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestResult {
+    pub name: String,
+    pub file: PathBuf,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub passed: bool,
+    pub skipped: Option<String>,
+    #[serde(
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub duration: Duration,
+    pub slow: bool,
+    /// Failed only because it exceeded `max_duration`
+    pub too_slow: bool,
+    /// Passed only after failing at least once (see `retries`)
+    #[serde(default)]
+    pub flaky: bool,
+    pub peak_memory: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub seed: Option<u64>,
+    pub actual: String,
+    pub expected: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub error: Option<String>,
+    pub warnings: Vec<String>,
+    pub repro: Option<String>,
+    pub artifacts: Option<PathBuf>,
+    /// Every run of the test, in order, if it was retried
+    #[serde(default)]
+    pub attempts: Vec<Attempt>,
+    /// Runs of `--repeat` whose output or exit code differed from the first run's
+    #[serde(default)]
+    pub diverged: Vec<u32>,
+}
+
+/// Outcome of one run of a retried test
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Attempt {
+    pub passed: bool,
+    #[serde(
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub duration: Duration,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+}
+
+impl Attempt {
+    /// Records the outcome of `result`
+    pub fn of(result: &TestResult) -> Self {
+        Attempt {
+            passed: result.passed,
+            duration: result.duration,
+            exit_code: result.exit_code,
+            error: result.error.clone(),
+        }
+    }
+}
+
+impl TestResult {
+    /// Creates a failed result for a test that could not be run
+    pub fn failure(test: &MarcoTestCase, error: impl Into<String>) -> Self {
+        TestResult {
+            name: test.header.name.clone(),
+            file: test.file.clone(),
+            tags: test.header.tags.clone(),
+            passed: false,
+            skipped: None,
+            duration: Duration::ZERO,
+            slow: false,
+            too_slow: false,
+            flaky: false,
+            peak_memory: None,
+            exit_code: None,
+            seed: None,
+            actual: String::new(),
+            expected: test.expected_output.clone(),
+            stdout: String::new(),
+            stderr: String::new(),
+            error: Some(error.into()),
+            warnings: vec![],
+            repro: None,
+            artifacts: None,
+            attempts: vec![],
+            diverged: vec![],
+        }
+    }
+
+    /// Creates a skipped result for a test that was not run
+    pub fn skipped(test: &MarcoTestCase, reason: impl Into<String>) -> Self {
+        TestResult {
+            skipped: Some(reason.into()),
+            error: None,
+            ..TestResult::failure(test, String::new())
+        }
+    }
+
+    /// Whether the test ran and did not pass
+    pub fn failed(&self) -> bool {
+        !self.passed && self.skipped.is_none()
+    }
+
+    /// `pass`, `fail` or `skip`, as machine-readable formats report it
+    pub fn status(&self) -> &'static str {
+        if self.skipped.is_some() {
+            "skip"
+        } else if self.passed {
+            "pass"
+        } else {
+            "fail"
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use shell_words;
+use similar::{Algorithm, ChangeTag, TextDiff};
+use std::borrow::Cow;
+use std::path::{Component, Path};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Whether console output keeps its ANSI colors, decided by `--color`
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Terminal escape sequences (CSI), e.g. `\x1b[91m`
+static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]").unwrap());
+
+/// Parses a commandline string into a program and its arguments
+#[allow(unused)]
+pub fn parse_shell_cmd(cmd: &str) -> Option<(String, Vec<String>)> {
+    match shell_words::split(cmd) {
+        Ok(words) if !words.is_empty() => {
+            let prog = words[0].clone();
+            let args = words[1..].to_vec();
+            Some((prog, args))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a human-readable duration such as `500ms`, `5s`, `2m` or `1h` (bare numbers are seconds)
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", s))?;
+    let secs = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        other => {
+            return Err(format!(
+                "unknown duration unit {:?} (use ms, s, m or h)",
+                other
+            ));
+        }
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration {:?}: {}", s, e))
+}
+
+/// Parses a `KEY=VALUE` command-line argument
+pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", s))?;
+    Ok((key.trim().to_ascii_lowercase(), value.trim().to_owned()))
+}
+
+/// Parses an environment variable assignment given as `KEY=VALUE`; the value may be empty
+pub fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_owned(), value.to_owned()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got {:?}", s)),
+    }
+}
+
+/// Parses a dotenv file: `KEY=VALUE` lines, optionally starting with `export`, with `#`
+/// comments. Values can be single-quoted (taken literally) or double-quoted (with `\n`, `\"`
+/// and `\\` escapes); unquoted values end at a ` #` comment.
+pub fn parse_dotenv(src: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line
+            .split_once('=')
+            .filter(|(key, _)| !key.trim().is_empty())
+        else {
+            return Err(format!(
+                "line {}: expected KEY=VALUE, got {:?}",
+                i + 1,
+                line
+            ));
+        };
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            quoted
+                .strip_suffix('\'')
+                .ok_or_else(|| format!("line {}: unterminated single quote", i + 1))?
+                .to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let quoted = quoted
+                .strip_suffix('"')
+                .ok_or_else(|| format!("line {}: unterminated double quote", i + 1))?;
+            let mut unescaped = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    unescaped.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('n') => unescaped.push('\n'),
+                    Some(escaped @ ('"' | '\\')) => unescaped.push(escaped),
+                    Some(other) => {
+                        unescaped.push('\\');
+                        unescaped.push(other);
+                    }
+                    None => unescaped.push('\\'),
+                }
+            }
+            unescaped
+        } else {
+            value
+                .split_once(" #")
+                .map_or(value, |(value, _)| value)
+                .trim_end()
+                .to_string()
+        };
+        vars.push((key.trim().to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// Replaces each `${NAME}` in `text` with `lookup(NAME)`, where `\${` stands for a literal
+/// `${`. A variable `lookup` does not know is an error rather than an empty string.
+pub fn interpolate_env(
+    text: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut interpolated = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        if let Some(escaped) = rest[..start].strip_suffix('\\') {
+            interpolated.push_str(escaped);
+            interpolated.push_str("${");
+            rest = after;
+            continue;
+        }
+        interpolated.push_str(&rest[..start]);
+        let end = after
+            .find('}')
+            .ok_or_else(|| "unterminated `${`".to_string())?;
+        let name = &after[..end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid variable name in `${{{}}}`", name));
+        }
+        let value = lookup(name).ok_or_else(|| format!("`${{{}}}` is not set", name))?;
+        interpolated.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Checks whether `name` describes the current platform by OS (`linux`, `macos`, `windows`),
+/// family (`unix`) or architecture (`x86_64`, `aarch64`)
+pub fn platform_matches(name: &str) -> bool {
+    use std::env::consts::{ARCH, FAMILY, OS};
+    let name = name.trim();
+    [OS, FAMILY, ARCH]
+        .iter()
+        .any(|current| current.eq_ignore_ascii_case(name))
+}
+
+/// Derives a stable per-test seed from the run seed and the test's file and name
+pub fn test_seed(run_seed: u64, file: &Path, name: &str) -> u64 {
+    let id = format!("{}\0{}", file.to_string_lossy(), name);
+    fnv1a(run_seed.to_le_bytes().iter().chain(id.as_bytes()))
+}
+
+/// 64-bit FNV-1a hash, which is stable across platforms and releases
+pub fn fnv1a<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Decodes hex digits into bytes, ignoring whitespace (e.g. `de ad be ef`)
+pub fn decode_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        anyhow::bail!("odd number of hex digits");
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)?;
+            u8::from_str_radix(pair, 16).map_err(|_| anyhow::anyhow!("invalid hex byte {:?}", pair))
+        })
+        .collect()
+}
+
+/// Decodes standard (RFC 4648) base64 into bytes, ignoring whitespace
+pub fn decode_base64(s: &str) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buf: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes().filter(|b| !b.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => anyhow::bail!("invalid base64 character {:?}", c as char),
+        };
+        buf = (buf << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buf >> bits) as u8);
+            buf &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Converts CRLF line endings to LF
+pub fn normalize_newlines(s: &str) -> String {
+    s.replace("\r\n", "\n")
+}
+
+/// Whether a path is relative and has no `..` components, so it cannot leave the directory
+/// it is joined to
+pub fn is_relative_inside(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Replaces characters that are not safe in file names with `_`
+pub fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Formats a byte count as mebibytes, e.g. `12.3 MB`
+pub fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// Deserializes an optional human-readable duration (see [`parse_duration`]) from a header field,
+/// or a number of seconds like `timeout: 5`
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Secs(f64),
+        Text(String),
+    }
+    let value: Option<Value> = serde::Deserialize::deserialize(deserializer)?;
+    value
+        .map(|value| match value {
+            Value::Secs(secs) => Duration::try_from_secs_f64(secs)
+                .map_err(|e| format!("invalid duration {}: {}", secs, e)),
+            Value::Text(s) => parse_duration(&s),
+        })
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Serializes a duration as fractional seconds, as used in JSON reports
+pub fn serialize_secs<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Inverse of `serialize_secs`
+pub fn deserialize_secs<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let secs: f64 = serde::Deserialize::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+/// Matches each line of `actual` in full against the regex on the same line of `patterns`.
+/// Returns whether every line matched, and `patterns` with each matching regex replaced by
+/// the line it matched, so a diff against `actual` only shows the lines that did not match.
+pub fn match_regex_lines(patterns: &str, actual: &str) -> Result<(bool, String), String> {
+    let lines: Vec<&str> = actual.lines().collect();
+    let mut matched = patterns.lines().count() == lines.len();
+    let mut shown = vec![];
+    for (i, pattern) in patterns.lines().enumerate() {
+        let re = Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+            format!(
+                "Invalid regex on line {} of the Expected Output block: {}",
+                i + 1,
+                e
+            )
+        })?;
+        match lines.get(i) {
+            Some(line) if re.is_match(line) => shown.push(*line),
+            _ => {
+                matched = false;
+                shown.push(pattern);
+            }
+        }
+    }
+    Ok((matched, shown.join("\n")))
+}
+
+/// Checks if a &str is probably JSON (by looking for `{` or `[`)
+pub fn is_json(s: &str) -> bool {
+    let s = s.trim();
+    s.starts_with('{') || s.starts_with('[')
+}
+
+/// Fast JSON normalization and comparison. Returns true if parsed JSONs are equal once the
+/// values at `ignore_paths` are removed from both.
+pub fn normalized_json_eq(a: &str, b: &str, ignore_paths: &[JsonPath]) -> bool {
+    match (parse_json(a, ignore_paths), parse_json(b, ignore_paths)) {
+        (Some(j1), Some(j2)) => j1 == j2,
+        _ => false,
+    }
+}
+
+/// Pretty prints JSON with sorted keys and without the values at `ignore_paths`.
+/// Returns None if the input is not valid JSON.
+pub fn pretty_json(s: &str, ignore_paths: &[JsonPath]) -> Option<String> {
+    let value = parse_json(s, ignore_paths)?;
+    serde_json::to_string_pretty(&value).ok()
+}
+
+fn parse_json(s: &str, ignore_paths: &[JsonPath]) -> Option<serde_json::Value> {
+    let mut value = serde_json::from_str(s).ok()?;
+    for path in ignore_paths {
+        path.remove(&mut value);
+    }
+    Some(value)
+}
+
+/// YAML normalization and comparison. Returns true if both documents hold the same data,
+/// regardless of key order, quoting or flow vs. block style.
+pub fn normalized_yaml_eq(a: &str, b: &str, ignore_paths: &[JsonPath]) -> bool {
+    match (parse_yaml(a, ignore_paths), parse_yaml(b, ignore_paths)) {
+        (Some(y1), Some(y2)) => y1 == y2,
+        _ => false,
+    }
+}
+
+/// Prints YAML in block style with sorted keys and without the values at `ignore_paths`.
+/// Returns None if the input is not valid YAML.
+pub fn pretty_yaml(s: &str, ignore_paths: &[JsonPath]) -> Option<String> {
+    let value = parse_yaml(s, ignore_paths)?;
+    serde_yml::to_string(&value).ok()
+}
+
+/// Reads YAML into a JSON value, which sorts mapping keys, with numbers and booleans turned into
+/// strings so quoting does not matter (`'1'`, `"1"` and `1` are the same string); documents
+/// with non-string keys are not supported
+fn parse_yaml(s: &str, ignore_paths: &[JsonPath]) -> Option<serde_json::Value> {
+    let mut value = serde_yml::from_str(s).ok()?;
+    stringify_scalars(&mut value);
+    for path in ignore_paths {
+        path.remove(&mut value);
+    }
+    Some(value)
+}
+
+fn stringify_scalars(value: &mut serde_json::Value) {
+    use serde_json::Value;
+    match value {
+        Value::Number(n) => *value = Value::String(n.to_string()),
+        Value::Bool(b) => *value = Value::String(b.to_string()),
+        Value::Array(items) => items.iter_mut().for_each(stringify_scalars),
+        Value::Object(map) => map.values_mut().for_each(stringify_scalars),
+        Value::Null | Value::String(_) => {}
+    }
+}
+
+/// A location in a JSON document, given as a JSONPath (`$.items[*].id`, `$['a key'][0]`)
+/// or as a JSON Pointer (`/items/0/id`)
+#[derive(Debug, Clone)]
+pub struct JsonPath(Vec<PathSegment>);
+
+#[derive(Debug, Clone)]
+enum PathSegment {
+    /// An object key, or an array index in a JSON Pointer
+    Key(String),
+    Index(usize),
+    /// Every member of an object or array
+    Wildcard,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("Invalid JSON path {:?}: {}", path, reason);
+        if path.is_empty() || path.starts_with('/') {
+            let segments = path
+                .split('/')
+                .skip(1)
+                .map(|s| PathSegment::Key(s.replace("~1", "/").replace("~0", "~")))
+                .collect();
+            return Ok(JsonPath(segments));
+        }
+        let Some(mut rest) = path.strip_prefix('$') else {
+            return Err(invalid("expected it to start with `$` or `/`"));
+        };
+        let mut segments = vec![];
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let segment = match &after[..end] {
+                    "" => return Err(invalid("empty key after `.`")),
+                    "*" => PathSegment::Wildcard,
+                    key => PathSegment::Key(key.to_string()),
+                };
+                segments.push(segment);
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(|| invalid("unclosed `[`"))?;
+                let inner = after[..end].trim();
+                let segment = if inner == "*" {
+                    PathSegment::Wildcard
+                } else if let Ok(index) = inner.parse() {
+                    PathSegment::Index(index)
+                } else if inner.len() >= 2
+                    && (inner.starts_with('\'') && inner.ends_with('\'')
+                        || inner.starts_with('"') && inner.ends_with('"'))
+                {
+                    PathSegment::Key(inner[1..inner.len() - 1].to_string())
+                } else {
+                    return Err(invalid("expected `*`, an index or a quoted key in `[]`"));
+                };
+                segments.push(segment);
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid("expected `.` or `[`"));
+            }
+        }
+        Ok(JsonPath(segments))
+    }
+
+    /// Removes every value the path selects from `value`; selected array elements are set to
+    /// null instead
+    pub fn remove(&self, value: &mut serde_json::Value) {
+        remove_at(value, &self.0);
+    }
+}
+
+fn remove_at(value: &mut serde_json::Value, path: &[PathSegment]) {
+    use serde_json::Value;
+    let Some((first, rest)) = path.split_first() else {
+        *value = Value::Null;
+        return;
+    };
+    let index = |key: &str, len: usize| key.parse::<usize>().ok().filter(|&i| i < len);
+    if rest.is_empty() {
+        match (first, value) {
+            (PathSegment::Key(key), Value::Object(map)) => {
+                map.remove(key);
+            }
+            // Array elements become null rather than being removed, so the indexes of other
+            // paths still point at the same elements
+            (PathSegment::Key(key), Value::Array(items)) => {
+                if let Some(i) = index(key, items.len()) {
+                    items[i] = Value::Null;
+                }
+            }
+            (PathSegment::Index(i), Value::Array(items)) if *i < items.len() => {
+                items[*i] = Value::Null;
+            }
+            (PathSegment::Wildcard, Value::Object(map)) => map.clear(),
+            (PathSegment::Wildcard, Value::Array(items)) => items.clear(),
+            _ => {}
+        }
+        return;
+    }
+    match (first, value) {
+        (PathSegment::Key(key), Value::Object(map)) => {
+            if let Some(child) = map.get_mut(key) {
+                remove_at(child, rest);
+            }
+        }
+        (PathSegment::Key(key), Value::Array(items)) => {
+            if let Some(i) = index(key, items.len()) {
+                remove_at(&mut items[i], rest);
+            }
+        }
+        (PathSegment::Index(i), Value::Array(items)) => {
+            if let Some(child) = items.get_mut(*i) {
+                remove_at(child, rest);
+            }
+        }
+        (PathSegment::Wildcard, Value::Object(map)) => {
+            map.values_mut().for_each(|child| remove_at(child, rest));
+        }
+        (PathSegment::Wildcard, Value::Array(items)) => {
+            items.iter_mut().for_each(|child| remove_at(child, rest));
+        }
+        _ => {}
+    }
+}
+
+/// Where a reproduction command runs
+pub enum ReproDir<'a> {
+    /// An existing directory
+    Path(&'a Path),
+    /// A fresh temporary directory, with the contents of a fixtures directory copied into it
+    Sandbox(Option<&'a Path>),
+}
+
+/// Builds a copy-pasteable one-liner that writes `files` into `dir`, sets `env`, runs the `setup` steps and then the runner
+/// there with `input` on stdin. With `clean_env` the commands run under `env -i` with nothing but `env`.
+#[cfg(not(windows))]
+pub fn repro_command(
+    dir: ReproDir,
+    files: &[(String, String)],
+    env: &[(String, String)],
+    clean_env: bool,
+    setup: &[&str],
+    runner: &str,
+    input: &[u8],
+) -> String {
+    let quote = |path: &Path| {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        shell_words::quote(&path.to_string_lossy()).into_owned()
+    };
+    let mut cd = match dir {
+        ReproDir::Path(dir) => format!("cd {}", quote(dir)),
+        ReproDir::Sandbox(None) => "cd \"$(mktemp -d)\"".to_string(),
+        ReproDir::Sandbox(Some(fixtures)) => {
+            format!("cd \"$(mktemp -d)\" && cp -R {}/. .", quote(fixtures))
+        }
+    };
+    for (path, content) in files {
+        let path = shell_words::quote(path);
+        cd = format!(
+            "{cd} && mkdir -p \"$(dirname {path})\" && printf '{}' > {path}",
+            printf_escape(content.as_bytes())
+        );
+    }
+    let vars = env
+        .iter()
+        .map(|(key, value)| format!("{key}={}", shell_words::quote(value)));
+    let mut prefix = String::new();
+    if clean_env {
+        prefix = std::iter::once("env -i".to_string())
+            .chain(vars)
+            .collect::<Vec<_>>()
+            .join(" ")
+            + " ";
+    } else {
+        for var in vars {
+            cd = format!("{cd} && export {var}");
+        }
+    }
+    for step in setup {
+        cd = format!("{cd} && {prefix}{step}");
+    }
+    let runner = format!("{prefix}{runner}");
+    if input.is_empty() {
+        return format!("{cd} && {runner} < /dev/null");
+    }
+    format!("{cd} && printf '{}' | {runner}", printf_escape(input))
+}
+
+/// Escapes bytes for a single-quoted `printf` format string; bytes that are not valid UTF-8
+/// (e.g. from a hex Input block) become octal escapes
+#[cfg(not(windows))]
+fn printf_escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for chunk in bytes.utf8_chunks() {
+        escaped += &chunk
+            .valid()
+            .replace('\\', "\\\\")
+            .replace('%', "%%")
+            .replace('\'', "'\\''")
+            .replace('\r', "\\r")
+            .replace('\n', "\\n");
+        for byte in chunk.invalid() {
+            escaped += &format!("\\{:03o}", byte);
+        }
+    }
+    escaped
+}
+
+/// Builds a copy-pasteable one-liner that writes `files` into `dir`, sets `env`, runs the `setup` steps and then the runner
+/// there with `input` on stdin. PowerShell cannot start a command with an empty environment, so `clean_env` is not reproduced.
+#[cfg(windows)]
+pub fn repro_command(
+    dir: ReproDir,
+    files: &[(String, String)],
+    env: &[(String, String)],
+    _clean_env: bool,
+    setup: &[&str],
+    runner: &str,
+    input: &[u8],
+) -> String {
+    let quote = |path: &Path| {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        format!("'{}'", path.to_string_lossy().replace('\'', "''"))
+    };
+    let sandbox = "Set-Location (New-Item -ItemType Directory (Join-Path ([IO.Path]::GetTempPath()) (New-Guid)))";
+    let mut cd = match dir {
+        ReproDir::Path(dir) => format!("Set-Location {}", quote(dir)),
+        ReproDir::Sandbox(None) => sandbox.to_string(),
+        ReproDir::Sandbox(Some(fixtures)) => format!(
+            "{sandbox}; Copy-Item -Recurse (Join-Path {} '*') .",
+            quote(fixtures)
+        ),
+    };
+    for (path, content) in files {
+        // -Force creates missing parent directories
+        cd = format!(
+            "{cd}; New-Item -Force -ItemType File -Path '{}' -Value '{}' | Out-Null",
+            path.replace('\'', "''"),
+            content.replace('\'', "''")
+        );
+    }
+    for (key, value) in env {
+        cd = format!("{cd}; $env:{key}='{}'", value.replace('\'', "''"));
+    }
+    for step in setup {
+        cd = format!("{cd}; {step}");
+    }
+    if input.is_empty() {
+        return format!("{cd}; {runner}");
+    }
+    // PowerShell pipes text, so bytes that are not valid UTF-8 cannot be reproduced exactly
+    let escaped = String::from_utf8_lossy(input)
+        .replace('`', "``")
+        .replace('"', "`\"")
+        .replace('$', "`$")
+        .replace('\r', "`r")
+        .replace('\n', "`n");
+    format!("{cd}; \"{escaped}\" | {runner}")
+}
+
+/// Turns colored console output on or off for the whole process
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Strips the colors from console output when they are turned off
+pub fn paint(s: &str) -> Cow<'_, str> {
+    if COLOR.load(Ordering::Relaxed) {
+        Cow::Borrowed(s)
+    } else {
+        strip_ansi(s)
+    }
+}
+
+/// Removes terminal escape sequences from text
+pub fn strip_ansi(s: &str) -> Cow<'_, str> {
+    ANSI_ESCAPE.replace_all(s, "")
+}
+
+/// SHA-256 digest of `bytes` as lowercase hex
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Renders bytes as rows of 16 with their offset, hex values and printable ASCII characters,
+/// so a line diff of two dumps shows the rows that differ side by side
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut rows = vec![];
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (j, byte) in chunk.iter().enumerate() {
+            if j == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x} ", byte));
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        rows.push(format!("{:08x}  {:<49} |{}|", i * 16, hex, ascii));
+    }
+    rows.join("\n")
+}
+
+/// Renders an uncolored unified diff between actual and expected output
+pub fn unified_diff(actual: &str, expected: &str, algorithm: Algorithm) -> String {
+    TextDiff::configure()
+        .algorithm(algorithm)
+        .diff_lines(actual.trim(), expected.trim())
+        .unified_diff()
+        .header("actual", "expected")
+        .to_string()
+}
+
+/// Pretty print text diff
+pub fn print_diff(actual: &str, expected: &str, algorithm: Algorithm) {
+    let diff = TextDiff::configure()
+        .algorithm(algorithm)
+        .diff_lines(actual.trim(), expected.trim());
+    for change in diff.iter_all_changes() {
+        let (tag_symbol, color) = match change.tag() {
+            ChangeTag::Delete => ("\x1b[91m-\x1b[0m ", "\x1b[97m"),
+            ChangeTag::Insert => ("\x1b[92m+\x1b[0m ", "\x1b[97m"),
+            ChangeTag::Equal => ("  ", "\x1b[90m"),
+        };
+        let line = format!("    {}{}{}\x1b[0m", color, tag_symbol, change);
+        print!("{}", paint(&line));
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use dom_query::Document;
+use markdown::mdast::Node;
+use markdown::{ParseOptions, to_html, to_mdast};
+
+use crate::parser::parse_test_markdown_html;
+use crate::types::TestHeader;
+
+/// A structural problem in a test file; `line` is 0 when it cannot be pinned to one
+pub struct Diagnostic {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Checks every file for structural problems without running anything, printing one
+/// `file:line: message` diagnostic per problem. Returns the number of problems found.
+pub fn validate_files(files: &[PathBuf]) -> Result<usize> {
+    let mut problems = 0;
+    for file in files {
+        let src =
+            fs::read_to_string(file).with_context(|| format!("Failed to read file {:?}", file))?;
+        for diagnostic in validate_file(file, &src) {
+            problems += 1;
+            if diagnostic.line > 0 {
+                println!(
+                    "{}:{}: {}",
+                    file.to_string_lossy(),
+                    diagnostic.line,
+                    diagnostic.message
+                );
+            } else {
+                println!("{}: {}", file.to_string_lossy(), diagnostic.message);
+            }
+        }
+    }
+    println!(
+        "Validated {} files: {} problems found",
+        files.len(),
+        problems
+    );
+    Ok(problems)
+}
+
+/// Checks the frontmatter, Input/Expected Output pairing and test names of one file
+pub fn validate_file(file: &Path, src: &str) -> Vec<Diagnostic> {
+    // The frontmatter is the first h2, as a `---` block renders like a setext heading
+    let Some(frontmatter_line) = frontmatter_line(src) else {
+        return vec![Diagnostic {
+            line: 1,
+            message: "missing frontmatter (a `---` block with at least a `name`)".to_string(),
+        }];
+    };
+    let frontmatter = Document::from(to_html(src))
+        .select("h2:first-of-type")
+        .text()
+        .to_string();
+    if let Err(e) = serde_yml::from_str::<TestHeader>(&frontmatter) {
+        // Report the error's line in the file rather than within the frontmatter
+        let line = e
+            .location()
+            .map_or(frontmatter_line, |l| frontmatter_line + l.line() - 1);
+        let message = e.to_string();
+        let message = message.split(" at line ").next().unwrap_or_default();
+        return vec![Diagnostic {
+            line,
+            message: format!("unparseable frontmatter: {}", message),
+        }];
+    }
+
+    let tests = match parse_test_markdown_html(file.to_path_buf(), src) {
+        Ok(tests) => tests,
+        Err(e) => {
+            return vec![Diagnostic {
+                line: 0,
+                message: e.to_string(),
+            }];
+        }
+    };
+    let mut diagnostics = vec![];
+    let mut first_lines: BTreeMap<&str, usize> = BTreeMap::new();
+    for test in &tests {
+        let name = test.header.name.as_str();
+        if let Some(first) = first_lines.get(name) {
+            diagnostics.push(Diagnostic {
+                line: test.block_start_line,
+                message: format!("duplicate test name {:?} (first at line {})", name, first),
+            });
+        } else {
+            first_lines.insert(name, test.block_start_line);
+        }
+    }
+    diagnostics
+}
+
+/// Line of the first level 2 heading, which holds the frontmatter
+fn frontmatter_line(src: &str) -> Option<usize> {
+    let tree = to_mdast(src, &ParseOptions::default()).ok()?;
+    tree.children()?.iter().find_map(|node| match node {
+        Node::Heading(h) if h.depth == 2 => Some(h.position.as_ref().map_or(1, |p| p.start.line)),
+        _ => None,
+    })
+}
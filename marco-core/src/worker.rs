@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ExitStatus, Output};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::ChildMonitor;
+use crate::runner::{ChildConfig, ChildOutcome, spawn_child};
+
+/// How long a worker gets to exit after its stdin is closed before it is killed
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// How long to wait for a response when neither the test nor the run has a timeout
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Long-lived runners of tests with `runner_mode: worker`. A worker is started once and then
+/// sent one JSON line per test, answering each with a JSON line of its own; tests with the
+/// same runner command, working directory and environment share idle workers, and more are
+/// started while all of them are busy.
+#[derive(Default)]
+pub struct Workers {
+    idle: Mutex<HashMap<WorkerKey, Vec<Worker>>>,
+    next_id: AtomicU64,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct WorkerKey {
+    runner: String,
+    dir: PathBuf,
+    /// A worker keeps the environment it was started with, except for `MARCO_SEED`, which
+    /// each request carries
+    env: Vec<(String, String)>,
+    clean_env: bool,
+    network: bool,
+}
+
+struct Worker {
+    child: Child,
+    monitor: ChildMonitor,
+    stdin: ChildStdin,
+    /// Lines the worker prints on stdout, without their line ending
+    responses: Receiver<Vec<u8>>,
+    stderr: Receiver<Vec<u8>>,
+}
+
+/// What a worker is sent for each test
+#[derive(Serialize)]
+struct Request<'a> {
+    id: u64,
+    name: &'a str,
+    input: &'a str,
+    env: BTreeMap<&'a str, &'a str>,
+}
+
+/// What a worker answers for each test
+#[derive(Deserialize)]
+struct Response {
+    id: u64,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+    #[serde(default)]
+    exit_code: i32,
+}
+
+enum Received {
+    Line(Vec<u8>),
+    Aborted,
+    TimedOut,
+    Closed,
+}
+
+impl Workers {
+    /// Sends a test's input to an idle worker, starting one with `runner_cmd` if there is
+    /// none, and waits for its response. A worker that misses the deadline, or
+    /// `RESPONSE_TIMEOUT` without one, or breaks the protocol is killed.
+    pub fn exchange(
+        &self,
+        runner_cmd: &str,
+        name: &str,
+        input: &[u8],
+        config: &ChildConfig,
+    ) -> Result<ChildOutcome, String> {
+        // JSON strings cannot carry arbitrary bytes
+        let input = std::str::from_utf8(input)
+            .map_err(|e| format!("A worker's input must be UTF-8 text: {}", e))?;
+        let key = WorkerKey {
+            runner: runner_cmd.to_string(),
+            dir: config.dir.to_path_buf(),
+            env: config
+                .env
+                .iter()
+                .filter(|(key, _)| key != "MARCO_SEED")
+                .cloned()
+                .collect(),
+            clean_env: config.clean_env,
+            network: config.network,
+        };
+        let idle = self.lock().get_mut(&key).and_then(Vec::pop);
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => Worker::spawn(runner_cmd, config)?,
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = Request {
+            id,
+            name,
+            input,
+            env: config
+                .env
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+        };
+        let mut line = serde_json::to_string(&request).expect("requests serialize");
+        line.push('\n');
+        let written = worker
+            .stdin
+            .write_all(line.as_bytes())
+            .and_then(|_| worker.stdin.flush());
+        let received = match written {
+            Ok(()) => worker.receive(config),
+            Err(_) => Received::Closed,
+        };
+        let line = match received {
+            Received::Line(line) => line,
+            Received::Aborted => {
+                let (status, stderr) = worker.kill();
+                let output = Output {
+                    status: status.map_err(|e| format!("Failed waiting on child: {}", e))?,
+                    stdout: vec![],
+                    stderr,
+                };
+                return Ok(ChildOutcome::Killed(output));
+            }
+            Received::TimedOut => {
+                let (_, stderr) = worker.kill();
+                return Err(format!(
+                    "Worker sent no response within {:?}; its stderr:\n{}",
+                    RESPONSE_TIMEOUT,
+                    String::from_utf8_lossy(&stderr)
+                ));
+            }
+            Received::Closed => {
+                let (status, stderr) = worker.kill();
+                return Err(format!(
+                    "Worker exited ({}) without answering; its stderr:\n{}",
+                    status.map_or_else(|e| e.to_string(), |status| status.to_string()),
+                    String::from_utf8_lossy(&stderr)
+                ));
+            }
+        };
+        let line = match String::from_utf8(line) {
+            Ok(line) => line,
+            Err(e) => {
+                let _ = worker.kill();
+                return Err(format!(
+                    "Worker sent a response that is not UTF-8 {:?}: {}",
+                    String::from_utf8_lossy(e.as_bytes()),
+                    e.utf8_error()
+                ));
+            }
+        };
+        let response = match serde_json::from_str::<Response>(&line) {
+            Ok(response) if response.id == id => response,
+            Ok(response) => {
+                let _ = worker.kill();
+                return Err(format!(
+                    "Worker answered request {} with the response to request {}",
+                    id, response.id
+                ));
+            }
+            Err(e) => {
+                let _ = worker.kill();
+                return Err(format!("Worker sent an invalid response {:?}: {}", line, e));
+            }
+        };
+
+        let mut stderr = response.stderr.into_bytes();
+        // What the worker itself logged while handling the test
+        stderr.extend(worker.stderr.try_iter().flatten());
+        self.lock().entry(key).or_default().push(worker);
+        let output = Output {
+            status: exit_status(response.exit_code),
+            stdout: response.stdout.into_bytes(),
+            stderr,
+        };
+        Ok(ChildOutcome::Exited(output, None))
+    }
+
+    /// Closes the stdin of every idle worker and waits briefly for them to exit, killing
+    /// those that do not
+    pub fn finish(&self) {
+        let workers: Vec<Worker> = self.lock().drain().flat_map(|(_, idle)| idle).collect();
+        for worker in workers {
+            worker.finish();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<WorkerKey, Vec<Worker>>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for Workers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Workers").finish_non_exhaustive()
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl Worker {
+    fn spawn(runner_cmd: &str, config: &ChildConfig) -> Result<Self, String> {
+        let (mut child, monitor) = spawn_child(runner_cmd, config)?;
+        let stdin = child.stdin.take().expect("runner stdin is piped");
+        let stdout = child.stdout.take().expect("runner stdout is piped");
+        let stderr = child.stderr.take();
+        let (tx, responses) = mpsc::channel();
+        thread::spawn(move || {
+            // Lines are read as bytes, so one that is not UTF-8 is reported rather than
+            // ending the worker's output
+            let mut stdout = BufReader::new(stdout);
+            loop {
+                let mut line = vec![];
+                if !matches!(stdout.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+                    break;
+                }
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let (tx, stderr_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let Some(mut stderr) = stderr else { return };
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = stderr.read(&mut buf) {
+                if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Worker {
+            child,
+            monitor,
+            stdin,
+            responses,
+            stderr: stderr_rx,
+        })
+    }
+
+    /// Waits for the worker's next line until the deadline, or `RESPONSE_TIMEOUT` without one,
+    /// or cancellation
+    fn receive(&self, config: &ChildConfig) -> Received {
+        let fallback = Instant::now() + RESPONSE_TIMEOUT;
+        let deadline = config.deadline.unwrap_or(fallback);
+        loop {
+            let mut wait = deadline.saturating_duration_since(Instant::now());
+            if config.cancelled.is_some() {
+                // Wake up regularly to notice cancellation
+                wait = wait.min(Duration::from_millis(50));
+            }
+            match self.responses.recv_timeout(wait) {
+                // Blank lines between responses are tolerated
+                Ok(line) if line.trim_ascii().is_empty() => {}
+                Ok(line) => return Received::Line(line),
+                Err(RecvTimeoutError::Timeout)
+                    if !config.should_abort() && Instant::now() < deadline => {}
+                Err(RecvTimeoutError::Timeout)
+                    if config.deadline.is_none() && !config.should_abort() =>
+                {
+                    return Received::TimedOut;
+                }
+                Err(RecvTimeoutError::Timeout) => return Received::Aborted,
+                Err(RecvTimeoutError::Disconnected) => return Received::Closed,
+            }
+        }
+    }
+
+    /// Kills the worker and everything it started, returning its exit status and stderr
+    fn kill(mut self) -> (std::io::Result<ExitStatus>, Vec<u8>) {
+        self.monitor.kill_tree(&mut self.child);
+        let status = self.child.wait();
+        (status, self.stderr.try_iter().flatten().collect())
+    }
+
+    fn finish(mut self) {
+        drop(self.stdin);
+        let until = Instant::now() + EXIT_GRACE;
+        while Instant::now() < until {
+            if let Ok(Some(_)) = self.monitor.try_wait(&mut self.child) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.monitor.kill_tree(&mut self.child);
+        let _ = self.child.wait();
+    }
+}
+
+/// The exit status a worker reports for a test
+fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        std::os::unix::process::ExitStatusExt::from_raw((code & 0xff) << 8)
+    }
+    #[cfg(windows)]
+    {
+        std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+    }
+}
@@ -0,0 +1,41 @@
+//! Failing tests with the same name in different files keep their own artifacts
+#![cfg(unix)]
+
+use std::env;
+use std::fs;
+use std::process;
+
+use marco_core::parser::parse_test_markdown_html;
+use marco_core::runner::{RunOptions, run_suite};
+
+#[test]
+fn same_test_names_in_different_files_do_not_share_artifacts() {
+    let dir = env::temp_dir().join(format!("marco-artifacts-{}", process::id()));
+    let mut tests = vec![];
+    for file in ["one", "two"] {
+        let src = format!(
+            "---\nname: Same\nrunner: cat\n---\n\n# Fails\n\nInput:\n\n```\n{file}\n```\n\nExpected Output:\n\n```\nnothing\n```\n"
+        );
+        fs::create_dir_all(dir.join(file)).unwrap();
+        let path = dir.join(file).join("same.marco.md");
+        tests.extend(parse_test_markdown_html(path, &src).unwrap());
+    }
+    let opts = RunOptions {
+        artifacts_dir: Some(dir.join("artifacts")),
+        ..RunOptions::default()
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(2)
+        .build()
+        .unwrap();
+    let suite = pool.install(|| run_suite(&tests, &opts, &()));
+
+    let mut stdouts = vec![];
+    for result in &suite.results {
+        assert!(!result.passed);
+        let artifacts = result.artifacts.as_ref().expect("artifacts are collected");
+        stdouts.push(fs::read_to_string(artifacts.join("stdout.txt")).unwrap());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(stdouts, ["one\n", "two\n"]);
+}
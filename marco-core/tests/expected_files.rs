@@ -0,0 +1,62 @@
+//! Expected Files must stay inside the test's working directory and artifacts directory
+#![cfg(unix)]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use marco_core::parser::parse_test_markdown_html;
+use marco_core::runner::{RunOptions, run_test_case};
+
+/// A failing test whose Expected Files block has the given fence
+fn source(fence: &str) -> String {
+    format!(
+        "---\nname: Files\nrunner: cat\n---\n\n## Files\n\nInput:\n\n```\nactual\n```\n\nExpected Output:\n\n```\nexpected\n```\n\n### Expected Files\n\n```{}\nkept\n```\n",
+        fence
+    )
+}
+
+#[test]
+fn paths_leaving_the_working_directory_are_rejected() {
+    for fence in [
+        "file=../outside/victim.txt",
+        "file=/tmp/victim.txt",
+        "text path=../victim.txt",
+    ] {
+        let parsed = parse_test_markdown_html(PathBuf::from("./files.marco.md"), &source(fence));
+        let error = parsed.expect_err(fence).to_string();
+        assert!(error.contains("must be relative"), "{}: {}", fence, error);
+    }
+}
+
+#[test]
+fn artifacts_never_touch_files_outside_their_directory() {
+    let dir = env::temp_dir().join(format!("marco-expected-files-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let victim = dir.join("victim.txt");
+    fs::write(&victim, "precious").unwrap();
+
+    let src = source("file=inside.txt");
+    let mut tests = parse_test_markdown_html(dir.join("files.marco.md"), &src).unwrap();
+    // As if the parser had let the absolute path through
+    tests[0].expected_files[0].0 = victim.to_string_lossy().into_owned();
+    let opts = RunOptions {
+        artifacts_dir: Some(dir.join("artifacts")),
+        ..RunOptions::default()
+    };
+    let result = run_test_case(&tests[0], &opts);
+    let contents = fs::read_to_string(&victim).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(!result.passed);
+    assert_eq!(contents, "precious");
+    assert!(
+        result
+            .warnings
+            .iter()
+            .any(|w| w.contains("leaves the working directory")),
+        "{:?}",
+        result.warnings
+    );
+}
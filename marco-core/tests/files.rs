@@ -0,0 +1,33 @@
+//! Tests writing the same Files into one directory must not race when run in parallel
+#![cfg(unix)]
+
+use std::env;
+use std::fs;
+use std::process;
+
+use marco_core::parser::parse_test_markdown_html;
+use marco_core::runner::{RunOptions, run_suite};
+
+#[test]
+fn tests_sharing_a_file_run_one_at_a_time() {
+    let dir = env::temp_dir().join(format!("marco-files-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut src = "---\nname: Files\nrunner: sh\n---\n".to_owned();
+    for n in 0..4 {
+        src.push_str(&format!(
+            "\n## Test {n}\n\n```text path=shared.txt\ntest {n}\n```\n\nInput:\n\n```\nsleep 0.1\ncat shared.txt\n```\n\nExpected Output:\n\n```\ntest {n}\n```\n"
+        ));
+    }
+    let tests = parse_test_markdown_html(dir.join("files.marco.md"), &src).unwrap();
+    assert_eq!(tests.len(), 4);
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+    let suite = pool.install(|| run_suite(&tests, &RunOptions::default(), &()));
+    fs::remove_dir_all(&dir).unwrap();
+    for result in suite.results {
+        assert!(result.passed, "{}: {:?}", result.name, result.error);
+    }
+}
@@ -0,0 +1,25 @@
+//! A failing `after_all` is an error of its file, not of the test that happened to finish last
+#![cfg(unix)]
+
+use std::env;
+
+use marco_core::parser::parse_test_markdown_html;
+use marco_core::runner::{RunOptions, run_suite};
+
+#[test]
+fn failed_after_all_fails_the_file_not_a_test() {
+    let src = "---\nname: Hooks\nrunner: cat\nafter_all: sh -c 'echo cleanup broke >&2; exit 3'\n---\n\n# First\n\nInput:\n\n```\none\n```\n\nExpected Output:\n\n```\none\n```\n\n# Second\n\nInput:\n\n```\ntwo\n```\n\nExpected Output:\n\n```\ntwo\n```\n";
+    let file = env::temp_dir().join("hooks.marco.md");
+    let tests = parse_test_markdown_html(file.clone(), src).unwrap();
+    assert_eq!(tests.len(), 2);
+
+    let suite = run_suite(&tests, &RunOptions::default(), &());
+    for result in &suite.results {
+        assert!(result.passed, "{}: {:?}", result.name, result.error);
+    }
+    let [(failed, error)] = suite.after_all_errors.as_slice() else {
+        panic!("expected one after_all error: {:?}", suite.after_all_errors);
+    };
+    assert_eq!(failed, &file);
+    assert!(error.contains("cleanup broke"), "{}", error);
+}
@@ -0,0 +1,41 @@
+//! Runners that echo a large input back must not deadlock on a full pipe
+#![cfg(unix)]
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use marco_core::parser::parse_test_markdown_html;
+use marco_core::runner::{RunOptions, run_test_case};
+
+/// Runs `runner` on `size` bytes of input, expecting them back on stdout
+fn run_echo(runner: &str, size: usize) {
+    let src = format!(
+        "---\nname: Large input\nrunner: {}\n---\n\n## Large input\n\nInput:\n\n```\nx\n```\n\nExpected Output:\n\n```\nx\n```\n",
+        runner
+    );
+    let mut tests =
+        parse_test_markdown_html(PathBuf::from("./large-input.marco.md"), &src).unwrap();
+    assert_eq!(tests.len(), 1);
+    // Set directly, as parsing a markdown file this large takes long in debug builds
+    let line = "the quick brown fox jumps over the lazy dog\n";
+    let text = line.repeat(size / line.len() + 1);
+    tests[0].input_data = text.clone();
+    tests[0].expected_output = text;
+
+    let opts = RunOptions {
+        timeout: Some(Duration::from_secs(60)),
+        ..RunOptions::default()
+    };
+    let result = run_test_case(&tests[0], &opts);
+    assert!(result.passed, "{:?}", result.error);
+}
+
+#[test]
+fn stdout_echoes_multi_megabyte_input() {
+    run_echo("cat", 4 << 20);
+}
+
+#[test]
+fn stdout_and_stderr_echo_multi_megabyte_input() {
+    run_echo("tee /dev/stderr", 4 << 20);
+}
@@ -0,0 +1,67 @@
+//! Masked secrets must not show up in what a failed test reports
+#![cfg(unix)]
+
+use std::path::PathBuf;
+
+use marco_core::parser::parse_test_markdown_html;
+use marco_core::runner::{RunOptions, run_test_case};
+use marco_core::types::{MarcoTestCase, TestResult};
+
+/// Parses a file with one test with the given header lines and `echo`ed input
+fn parse(header: &str, input: &str) -> MarcoTestCase {
+    let src = format!(
+        "---\nname: Mask\nrunner: sh\n{}\n---\n\n## Mask\n\nInput:\n\n```\n{}\n```\n\nExpected Output:\n\n```\nsomething else\n```\n",
+        header, input
+    );
+    let mut tests = parse_test_markdown_html(PathBuf::from("./mask.marco.md"), &src).unwrap();
+    assert_eq!(tests.len(), 1);
+    tests.remove(0)
+}
+
+/// Runs the single test of a file with the given header lines and `echo`ed input
+fn run(header: &str, input: &str) -> TestResult {
+    run_test_case(&parse(header, input), &RunOptions::default())
+}
+
+#[test]
+fn failed_output_is_masked() {
+    let result = run(
+        "env: { API_TOKEN: s3cr3t }\nmask: [\"env:API_TOKEN\", \"tok_[a-z0-9]+\"]",
+        "echo \"Bearer $API_TOKEN tok_abc123\"",
+    );
+    assert!(!result.passed);
+    for text in [&result.actual, &result.stdout] {
+        assert_eq!(text.trim(), "Bearer *** ***");
+    }
+    let repro = result.repro.unwrap();
+    assert!(repro.contains("API_TOKEN=***"), "{}", repro);
+    assert!(!repro.contains("s3cr3t"), "{}", repro);
+}
+
+#[test]
+fn bare_words_are_regexes() {
+    let result = run("mask: [password]", "echo \"password: hunter2\"");
+    assert_eq!(result.stdout.trim(), "***: hunter2");
+}
+
+#[test]
+fn unset_variable_fails_the_test() {
+    let result = run(
+        "mask: [\"env:MARCO_MASK_TEST_UNSET\"]",
+        "echo \"password: hunter2\"",
+    );
+    assert!(!result.passed);
+    assert!(
+        result.error.as_deref().unwrap().contains("not set"),
+        "{:?}",
+        result.error
+    );
+}
+
+#[test]
+fn masked_output_is_never_written_back() {
+    // `marco update` and `--interactive` would store `***` as the Expected Output
+    let test = parse("mask: [hunter2]", "echo \"password: hunter2\"");
+    assert_eq!(test.expected_block, None);
+    assert!(parse("timeout: 5s", "echo hi").expected_block.is_some());
+}
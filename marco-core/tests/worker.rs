@@ -0,0 +1,27 @@
+//! Workers that break the protocol fail the test with an error saying how
+#![cfg(unix)]
+
+use std::path::PathBuf;
+
+use marco_core::parser::parse_test_markdown_html;
+use marco_core::runner::{RunOptions, run_test_case};
+use marco_core::types::TestResult;
+
+/// Runs a single test against a worker started with the given runner
+fn run(runner: &str) -> TestResult {
+    let src = format!(
+        "---\nname: Worker\nrunner: '{}'\nrunner_mode: worker\ntimeout: 5s\n---\n\n## Worker\n\nInput:\n\n```\nhello\n```\n\nExpected Output:\n\n```\nHELLO\n```\n",
+        runner.replace('\'', "''")
+    );
+    let tests = parse_test_markdown_html(PathBuf::from("./worker.marco.md"), &src).unwrap();
+    assert_eq!(tests.len(), 1);
+    run_test_case(&tests[0], &RunOptions::default())
+}
+
+#[test]
+fn response_that_is_not_utf8_is_a_protocol_error() {
+    let result = run(r#"sh -c 'read request; printf "\377\n"; sleep 5'"#);
+    assert!(!result.passed);
+    let error = result.error.unwrap();
+    assert!(error.contains("not UTF-8"), "{}", error);
+}
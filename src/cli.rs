@@ -1,29 +1,507 @@
-use clap::Parser;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use marco_core::jobs::JobSlots;
+use marco_core::runner::RunOptions;
+use marco_core::types::{MarcoTestCase, Shell};
+use marco_core::util::{self, parse_duration, parse_env_var, parse_key_value};
+
+use crate::config::Config;
 
 #[derive(Parser, Debug, Clone)]
 pub struct Args {
+    /// What to do; without a command the tests are run
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Read option defaults from this file instead of the nearest marco.toml (user config still applies)
+    #[clap(long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
     /// Glob or direct file for test collection
-    #[clap(short, long, default_value = "**/*.marco.md")]
+    #[clap(short, long, default_value = "**/*.marco.md", global = true)]
     pub input: String,
 
+    /// Only run tests whose name matches this regex
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new, global = true)]
+    pub filter: Option<Regex>,
+
+    /// Only run the test with exactly this name (repeatable)
+    #[clap(long, value_name = "NAME", global = true)]
+    pub exact: Vec<String>,
+
+    /// Only run tests with this tag (repeatable; any of them selects a test)
+    #[clap(long, value_name = "TAG", global = true)]
+    pub tag: Vec<String>,
+
+    /// Don't run tests with this tag (repeatable)
+    #[clap(long, value_name = "TAG", global = true)]
+    pub skip_tag: Vec<String>,
+
     /// Command to run the tests with (overridden by "runner" field in test header)
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     pub runner: Option<String>,
 
+    /// Use --runner even for tests whose header sets a runner
+    #[clap(long, default_value_t = false, global = true)]
+    pub force_runner: bool,
+
+    /// Runner for tests without one, by Input fence language or extension, ahead of --runner (e.g. py=python3 -, repeatable)
+    #[clap(long = "lang-runner", value_name = "LANG=CMD", value_parser = parse_key_value, global = true)]
+    pub lang_runners: Vec<(String, String)>,
+
     /// Maximum number of threads to use in parallel (default: num_cpus)
-    #[clap(long, env = "MARCO_MAX_THREADS", value_name = "N")]
+    #[clap(long, env = "MARCO_MAX_THREADS", value_name = "N", global = true)]
     pub threads: Option<usize>,
 
-    /// Verbose output
-    #[clap(short, long, default_value_t = false)]
-    pub verbose: bool,
+    /// Maximum number of tests whose runners run at once, independent of --threads (default: no limit)
+    #[clap(long, env = "MARCO_JOBS", value_name = "N", global = true)]
+    pub jobs: Option<usize>,
+
+    /// Fail tests that would otherwise only produce warnings (e.g. empty Expected Output)
+    #[clap(long, default_value_t = false, global = true)]
+    pub strict: bool,
+
+    /// Seed from which each test's MARCO_SEED / `{seed}` is derived (default: random, printed)
+    #[clap(long, env = "MARCO_RUN_SEED", value_name = "N", global = true)]
+    pub seed: Option<u64>,
+
+    /// Set an environment variable for every test's runner, unless its header's `env` sets it (repeatable)
+    #[clap(long, value_name = "KEY=VAL", value_parser = parse_env_var, global = true)]
+    pub env: Vec<(String, String)>,
+
+    /// Load a dotenv file into every test's environment, before --env and the headers' `env_file` and `env` (repeatable)
+    #[clap(long, value_name = "PATH", global = true)]
+    pub env_file: Vec<PathBuf>,
+
+    /// Start runners with an empty environment plus `--pass-env`, `--env` and MARCO_SEED, unless their header sets `clean_env`
+    #[clap(long, default_value_t = false, global = true)]
+    pub clean_env: bool,
+
+    /// Keep this variable of marco's environment under --clean-env (repeatable; headers can add more with `pass_env`)
+    #[clap(long, value_name = "NAME", global = true)]
+    pub pass_env: Vec<String>,
+
+    /// Run tests without network access unless their header sets `network: true`
+    #[clap(long, default_value_t = false, global = true)]
+    pub no_network: bool,
+
+    /// Remove terminal escape sequences from the output of tests before comparing it, unless their header sets `strip_ansi`
+    #[clap(long, default_value_t = false, global = true)]
+    pub strip_ansi: bool,
+
+    /// Shell to run runner commands with, unless a test's header sets `shell` (default: powershell on Windows, none elsewhere)
+    #[clap(long, value_enum, env = "MARCO_SHELL", global = true)]
+    pub shell: Option<ShellChoice>,
+
+    /// Algorithm used to compute diffs for failed tests
+    #[clap(long, value_enum, default_value_t = DiffAlgorithm::Myers, global = true)]
+    pub diff_algorithm: DiffAlgorithm,
+
+    /// Flag tests that take longer than this as slow, without failing them (e.g. 5s, 500ms)
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    pub slow_threshold: Option<Duration>,
+
+    /// Kill a test's runner and every process it started after this long, unless its header sets `timeout`
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    pub timeout: Option<Duration>,
+
+    /// Abort the whole run after this long, killing running tests and writing partial reports (e.g. 20m)
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    pub run_timeout: Option<Duration>,
+
+    /// Rerun a failing test up to N times, unless its header sets `retries`; tests that pass on a rerun are reported as flaky
+    #[clap(long, value_name = "N", global = true)]
+    pub retries: Option<u32>,
+
+    /// Run each test N times, failing it if a run fails or its output differs from the first run's
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), global = true)]
+    pub repeat: Option<u32>,
+
+    /// Run each test twice (or --repeat times) to catch runners whose output is not deterministic
+    #[clap(long, default_value_t = false, global = true)]
+    pub check_determinism: bool,
+
+    /// Run the tests in a random order derived from SEED (default: the run's seed); `order` headers still apply
+    #[clap(long, value_name = "SEED", num_args = 0..=1, require_equals = true, global = true)]
+    pub shuffle: Option<Option<u64>>,
+
+    /// Only run shard K of N (e.g. 2/4); tests are split by a stable hash of their file and name
+    #[clap(long, value_name = "K/N", global = true)]
+    pub shard: Option<Shard>,
+
+    /// Only run the tests that failed when they last ran (all tests if none did)
+    #[clap(long, default_value_t = false, global = true)]
+    pub last_failed: bool,
+
+    /// Run the tests that failed when they last ran before the others
+    #[clap(long, default_value_t = false, global = true)]
+    pub failed_first: bool,
+
+    /// After the run, review each output mismatch and write accepted outputs back into the test files
+    #[clap(long, default_value_t = false, conflicts_with_all = ["format", "porcelain", "events"], global = true)]
+    pub interactive: bool,
+
+    /// Only collect the tests and list them, like `marco list`, without running anything
+    #[clap(long, default_value_t = false, global = true)]
+    pub dry_run: bool,
+
+    /// Stop at the first failing test, killing running ones, and only print that failure
+    #[clap(long, default_value_t = false, global = true)]
+    pub fail_fast: bool,
+
+    /// Only print the per-file summary table instead of every test result
+    #[clap(long, default_value_t = false, global = true)]
+    pub summary_only: bool,
+
+    /// Format of the results printed to stdout
+    #[clap(long, value_enum, default_value_t = OutputFormat::Human, global = true)]
+    pub format: OutputFormat,
+
+    /// Stream one JSON object per event (test started, test finished, ...) to stdout as they happen
+    #[clap(long, value_enum, value_name = "FORMAT", conflicts_with_all = ["format", "porcelain"], global = true)]
+    pub events: Option<EventFormat>,
+
+    /// Where to report results, comma-separated: console (--format on stdout), json (the JSON report on stdout), quiet (nothing on stdout), junit, html
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "NAME,...",
+        global = true
+    )]
+    pub reporter: Vec<ReporterKind>,
+
+    /// Print stable, tab-separated result and summary records (same as --format porcelain)
+    #[clap(long, default_value_t = false, global = true)]
+    pub porcelain: bool,
+
+    /// When to color console output (`auto` colors a terminal unless NO_COLOR is set)
+    #[clap(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto, global = true)]
+    pub color: ColorChoice,
+
+    /// Write an uncolored transcript of the run, including child output, to this file
+    #[clap(long, value_name = "PATH", global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Write the output, diff and files of each failing test into a subdirectory of this directory
+    #[clap(long, value_name = "DIR", global = true)]
+    pub artifacts_dir: Option<PathBuf>,
+
+    /// Write a JUnit XML report, including captured stdout/stderr, to this file
+    #[clap(long, value_name = "PATH", global = true)]
+    pub report_junit: Option<PathBuf>,
+
+    /// Write a self-contained HTML report with collapsible per-test sections and diffs to this file
+    #[clap(long, value_name = "PATH", global = true)]
+    pub report_html: Option<PathBuf>,
+
+    /// Append a Markdown summary with a results table and collapsible diffs to this file
+    #[clap(long, value_name = "PATH", env = "GITHUB_STEP_SUMMARY", global = true)]
+    pub summary_md: Option<PathBuf>,
+
+    /// Write a JSON report of all results, which `marco report` can combine, to this file
+    #[clap(long, value_name = "PATH", global = true)]
+    pub report_json: Option<PathBuf>,
+
+    /// Exit successfully as long as no more than N tests fail
+    #[clap(long, value_name = "N", global = true)]
+    pub max_failures: Option<usize>,
+
+    /// Exit successfully even if tests fail (errors and missing tests still fail the run)
+    #[clap(long, default_value_t = false, global = true)]
+    pub allow_failures: bool,
+
+    /// Verbose output (repeat for more detail, e.g. -vv)
+    #[clap(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// How the tests are run, from the options above; the runner aliases and case and
+    /// whitespace options only come from the config files
+    #[clap(skip)]
+    pub run: RunOptions,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run the tests (the default)
+    Run,
+    /// List the collected tests with their file, line, runner and tags without running them
+    List(ListArgs),
+    /// Check test files for structural problems (frontmatter, unmatched blocks, duplicate names) without running them
+    Validate,
+    /// Rewrite test files into the canonical layout (frontmatter key order, headings, fences, blank lines)
+    Fmt(FmtArgs),
+    /// Run the tests and write the actual output of every output mismatch into its Expected Output block
+    Update,
+    /// Merge JSON reports (e.g. from parallel CI jobs) into one, then write any other report from it
+    #[clap(visible_alias = "merge")]
+    Report(ReportArgs),
+    /// Create a skeleton test file with frontmatter and an Input/Expected Output pair (uses --runner)
+    New(NewArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ListArgs {
+    /// Print the tests as a JSON array
+    #[clap(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FmtArgs {
+    /// Don't write anything; list the files that are not formatted and fail if there are any
+    #[clap(long, default_value_t = false)]
+    pub check: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct NewArgs {
+    /// Name of the tests, also used for the file name
+    pub name: String,
+
+    /// Author recorded in the frontmatter
+    #[clap(long)]
+    pub author: Option<String>,
+
+    /// Directory to create the file in
+    #[clap(long, value_name = "DIR", default_value = ".")]
+    pub dir: PathBuf,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ReportArgs {
+    /// JSON reports written with `--report-json`
+    #[clap(required = true, value_name = "REPORT")]
+    pub reports: Vec<PathBuf>,
+
+    /// Write the merged JSON report to this file
+    #[clap(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Colored results, diffs and a per-file summary
+    Human,
+    /// Stable, tab-separated records for scripts (see README)
+    Porcelain,
+    /// Test Anything Protocol version 13
+    Tap,
+    /// TeamCity service messages, printed live as each test starts and finishes
+    Teamcity,
+    /// libtest's JSON events, like `cargo test -- --format json`, printed live for tools that read them
+    #[serde(rename = "libtest-json")]
+    LibtestJson,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReporterKind {
+    /// Results in the `--format` chosen, on stdout (the default)
+    Console,
+    /// The JSON report of `--report-json`, on stdout
+    Json,
+    /// Nothing on stdout, e.g. when only report files are wanted
+    Quiet,
+    /// A JUnit XML report, to `--report-junit` or marco-junit.xml
+    Junit,
+    /// An HTML report, to `--report-html` or marco-report.html
+    Html,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFormat {
+    /// Newline-delimited JSON
+    Ndjson,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffAlgorithm {
+    Myers,
+    Patience,
+    Lcs,
+}
+
+impl From<DiffAlgorithm> for similar::Algorithm {
+    fn from(algorithm: DiffAlgorithm) -> Self {
+        match algorithm {
+            DiffAlgorithm::Myers => similar::Algorithm::Myers,
+            DiffAlgorithm::Patience => similar::Algorithm::Patience,
+            DiffAlgorithm::Lcs => similar::Algorithm::Lcs,
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellChoice {
+    Pwsh,
+    Powershell,
+    Cmd,
+    Bash,
+}
+
+impl From<ShellChoice> for Shell {
+    fn from(shell: ShellChoice) -> Self {
+        match shell {
+            ShellChoice::Pwsh => Shell::Pwsh,
+            ShellChoice::Powershell => Shell::Powershell,
+            ShellChoice::Cmd => Shell::Cmd,
+            ShellChoice::Bash => Shell::Bash,
+        }
+    }
+}
+
+/// Part K of N of the suite, selected with `--shard K/N`
+#[derive(Debug, Clone, Copy)]
+pub struct Shard {
+    /// 1-based
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// Whether a test belongs to this shard. The hash only depends on the test's file path
+    /// (with `/` separators) and name, so every machine and release splits a suite the same way.
+    pub fn contains(&self, test: &MarcoTestCase) -> bool {
+        let file = test.file.to_string_lossy().replace('\\', "/");
+        let id = format!("{}\0{}", file, test.header.name);
+        util::fnv1a(id.as_bytes()) % self.count == self.index - 1
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected K/N, e.g. 1/4, got '{}'", s))?;
+        let index: u64 = index
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid shard '{}': {}", index, e))?;
+        let count: u64 = count
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid shard count '{}': {}", count, e))?;
+        if index == 0 || index > count {
+            return Err(format!(
+                "shard must be between 1 and {}, got {}",
+                count, index
+            ));
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
 }
 
 impl Args {
+    /// Parses the command line, taking defaults for the options it does not set from the config files
+    pub fn parse_with_config() -> Result<Self, String> {
+        let matches = Args::command().get_matches();
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        Config::load(args.config.as_deref())?.apply(&mut args, &matches);
+        if args.force_runner && args.runner.is_none() {
+            return Err("--force-runner needs a runner from --runner or a config file".to_string());
+        }
+        Ok(args)
+    }
+
     pub fn set_defaults(mut self) -> Self {
         if self.input.is_empty() {
             self.input = "**/*.marco.md".to_owned();
         }
+        if self.seed.is_none() {
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            self.seed = Some(nanos as u64 ^ u64::from(std::process::id()));
+        }
+        if self.porcelain {
+            self.format = OutputFormat::Porcelain;
+        }
+        if self.check_determinism && self.repeat.is_none() {
+            self.repeat = Some(2);
+        }
+        self.run = RunOptions {
+            runner: self.runner.clone(),
+            force_runner: self.force_runner,
+            lang_runners: self.lang_runners.clone(),
+            strict: self.strict,
+            seed: self.seed,
+            env: self.env.clone(),
+            env_files: self.env_file.clone(),
+            clean_env: self.clean_env,
+            pass_env: self.pass_env.clone(),
+            no_network: self.no_network,
+            strip_ansi: self.strip_ansi,
+            shell: self.shell.map(Into::into),
+            slow_threshold: self.slow_threshold,
+            timeout: self.timeout,
+            retries: self.retries,
+            repeat: self.repeat,
+            fail_fast: self.fail_fast,
+            artifacts_dir: self.artifacts_dir.clone(),
+            diff_algorithm: self.diff_algorithm.into(),
+            deadline: self.run_timeout.map(|timeout| Instant::now() + timeout),
+            jobs: self.jobs.map(|jobs| Arc::new(JobSlots::new(jobs))),
+            ..std::mem::take(&mut self.run)
+        };
+        util::set_color(match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        });
         self
     }
+
+    /// Whether stdout is reserved for machine-readable results, moving progress messages to stderr
+    pub fn machine_output(&self) -> bool {
+        self.format != OutputFormat::Human
+            || self.events.is_some()
+            || self.reporter.contains(&ReporterKind::Json)
+            || matches!(&self.command, Some(Command::List(list)) if list.json)
+    }
+
+    /// Whether a test is selected by `--filter`, `--exact`, `--tag` and `--skip-tag`
+    pub fn selects(&self, test: &MarcoTestCase) -> bool {
+        let name = &test.header.name;
+        let tags = &test.header.tags;
+        self.filter.as_ref().is_none_or(|re| re.is_match(name))
+            && (self.exact.is_empty() || self.exact.iter().any(|exact| exact == name))
+            && (self.tag.is_empty() || self.tag.iter().any(|tag| tags.contains(tag)))
+            && !self.skip_tag.iter().any(|tag| tags.contains(tag))
+    }
+
+    /// Seed of the random test order, if `--shuffle` is given
+    pub fn shuffle_seed(&self) -> Option<u64> {
+        self.shuffle
+            .map(|seed| seed.or(self.seed).unwrap_or_default())
+    }
 }
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+
+use marco_core::normalize::TextOptions;
+use marco_core::util::deserialize_duration;
+
+use crate::cli::{Args, ColorChoice, OutputFormat, ShellChoice};
+
+/// Name of the project config file, looked up from the working directory upwards
+pub const CONFIG_FILE_NAME: &str = "marco.toml";
+
+/// Defaults for command line options, read from the user's `config.toml` and the project's
+/// `marco.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Glob for test collection, relative to the directory of the config file
+    pub input: Option<String>,
+    pub runner: Option<String>,
+    pub threads: Option<usize>,
+    pub jobs: Option<usize>,
+    pub shell: Option<ShellChoice>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub run_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub slow_threshold: Option<Duration>,
+    pub retries: Option<u32>,
+    pub format: Option<OutputFormat>,
+    pub color: Option<ColorChoice>,
+    pub report_junit: Option<PathBuf>,
+    pub report_html: Option<PathBuf>,
+    pub report_json: Option<PathBuf>,
+    pub ignore_case: Option<bool>,
+    pub ignore_trailing_whitespace: Option<bool>,
+    pub squash_whitespace: Option<bool>,
+    pub ignore_blank_lines: Option<bool>,
+    /// Runner commands by alias, e.g. `py = "python3 -"` lets tests use `runner: py`
+    #[serde(default)]
+    pub runner_aliases: BTreeMap<String, String>,
+    /// Runners for tests without one by Input fence language or extension, e.g.
+    /// `sql = "sqlite3 test.db"`; `--lang-runner` adds to them
+    #[serde(default)]
+    pub lang_runners: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Reads the user config, then the config file given with `--config` (or else the nearest
+    /// `marco.toml`) on top of it. Missing files are skipped, except one given with `--config`.
+    pub fn load(explicit: Option<&Path>) -> Result<Config, String> {
+        let user = match user_config_file().filter(|path| path.is_file()) {
+            Some(path) => Config::read(&path)?,
+            None => Config::default(),
+        };
+        let project = match explicit.map(Path::to_path_buf).or_else(find_config_file) {
+            Some(path) => Config::read(&path)?,
+            None => Config::default(),
+        };
+        Ok(project.or(user))
+    }
+
+    fn read(path: &Path) -> Result<Config, String> {
+        let src = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {:?}: {}", path, e))?;
+        let mut config: Config =
+            toml::from_str(&src).map_err(|e| format!("Invalid config file {:?}: {}", path, e))?;
+        // Keep globs short when the config file is in the working directory
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let in_cwd = dir.as_os_str().is_empty() || env::current_dir().is_ok_and(|cwd| cwd == dir);
+        if let Some(input) = &mut config.input
+            && !in_cwd
+            && !Path::new(input.as_str()).is_absolute()
+        {
+            *input = dir.join(&input).to_string_lossy().into_owned();
+        }
+        Ok(config)
+    }
+
+    /// Takes every value from `self` and falls back to `lower` for the ones it does not set
+    fn or(self, lower: Config) -> Config {
+        let mut runner_aliases = lower.runner_aliases;
+        runner_aliases.extend(self.runner_aliases);
+        let mut lang_runners = lower.lang_runners;
+        lang_runners.extend(self.lang_runners);
+        Config {
+            input: self.input.or(lower.input),
+            runner: self.runner.or(lower.runner),
+            threads: self.threads.or(lower.threads),
+            jobs: self.jobs.or(lower.jobs),
+            shell: self.shell.or(lower.shell),
+            timeout: self.timeout.or(lower.timeout),
+            run_timeout: self.run_timeout.or(lower.run_timeout),
+            slow_threshold: self.slow_threshold.or(lower.slow_threshold),
+            retries: self.retries.or(lower.retries),
+            format: self.format.or(lower.format),
+            color: self.color.or(lower.color),
+            report_junit: self.report_junit.or(lower.report_junit),
+            report_html: self.report_html.or(lower.report_html),
+            report_json: self.report_json.or(lower.report_json),
+            ignore_case: self.ignore_case.or(lower.ignore_case),
+            ignore_trailing_whitespace: self
+                .ignore_trailing_whitespace
+                .or(lower.ignore_trailing_whitespace),
+            squash_whitespace: self.squash_whitespace.or(lower.squash_whitespace),
+            ignore_blank_lines: self.ignore_blank_lines.or(lower.ignore_blank_lines),
+            runner_aliases,
+            lang_runners,
+        }
+    }
+
+    /// Fills in every option that was neither given on the command line nor by environment variable
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        if let Some(input) = self.input
+            && is_unset(matches, "input")
+        {
+            args.input = input;
+        }
+        if self.runner.is_some() && is_unset(matches, "runner") {
+            args.runner = self.runner;
+        }
+        if self.threads.is_some() && is_unset(matches, "threads") {
+            args.threads = self.threads;
+        }
+        if self.jobs.is_some() && is_unset(matches, "jobs") {
+            args.jobs = self.jobs;
+        }
+        if self.shell.is_some() && is_unset(matches, "shell") {
+            args.shell = self.shell;
+        }
+        if self.timeout.is_some() && is_unset(matches, "timeout") {
+            args.timeout = self.timeout;
+        }
+        if self.run_timeout.is_some() && is_unset(matches, "run_timeout") {
+            args.run_timeout = self.run_timeout;
+        }
+        if self.slow_threshold.is_some() && is_unset(matches, "slow_threshold") {
+            args.slow_threshold = self.slow_threshold;
+        }
+        if self.retries.is_some() && is_unset(matches, "retries") {
+            args.retries = self.retries;
+        }
+        // `--events` and `--interactive` rule out other formats
+        if let Some(format) = self.format
+            && is_unset(matches, "format")
+            && args.events.is_none()
+            && !args.interactive
+        {
+            args.format = format;
+        }
+        if let Some(color) = self.color
+            && is_unset(matches, "color")
+        {
+            args.color = color;
+        }
+        if self.report_junit.is_some() && is_unset(matches, "report_junit") {
+            args.report_junit = self.report_junit;
+        }
+        if self.report_html.is_some() && is_unset(matches, "report_html") {
+            args.report_html = self.report_html;
+        }
+        if self.report_json.is_some() && is_unset(matches, "report_json") {
+            args.report_json = self.report_json;
+        }
+        args.run.text_options = TextOptions {
+            ignore_case: self.ignore_case,
+            ignore_trailing_whitespace: self.ignore_trailing_whitespace,
+            squash_whitespace: self.squash_whitespace,
+            ignore_blank_lines: self.ignore_blank_lines,
+        };
+        args.run.runner_aliases = self.runner_aliases;
+        // Put first, so the `--lang-runner` mappings for the same languages win
+        let mut lang_runners: Vec<(String, String)> = self.lang_runners.into_iter().collect();
+        lang_runners.append(&mut args.lang_runners);
+        args.lang_runners = lang_runners;
+    }
+}
+
+/// Whether an option only has its default value
+fn is_unset(matches: &ArgMatches, id: &str) -> bool {
+    !matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+/// The user's config file, `$XDG_CONFIG_HOME/marco/config.toml` (by default under `~/.config`)
+/// or `%APPDATA%\marco\config.toml` on Windows
+fn user_config_file() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let dir = env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    dir.map(|dir| dir.join("marco").join("config.toml"))
+}
+
+/// Walks up from the working directory to the first directory with a `marco.toml`
+fn find_config_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
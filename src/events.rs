@@ -0,0 +1,17 @@
+use serde_json::{Value, json};
+
+use crate::cli::Args;
+
+/// Prints an event as one JSON line if `--events ndjson` is set.
+/// `fields` must be a JSON object; the event name is added to it as `"event"`.
+pub fn emit(args: &Args, event: &str, fields: Value) {
+    if args.events.is_none() {
+        return;
+    }
+    let mut object = json!({ "event": event });
+    if let (Some(object), Value::Object(fields)) = (object.as_object_mut(), fields) {
+        object.extend(fields);
+    }
+    // A single println! keeps lines from concurrently finishing tests whole
+    println!("{}", object);
+}
@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+/// Frontmatter keys in canonical order, as declared on `TestHeader`; unknown keys go last
+const HEADER_KEY_ORDER: &[&str] = &[
+    "name",
+    "author",
+    "runner",
+    "runners",
+    "shell",
+    "exec",
+    "matrix",
+    "max_memory_mb",
+    "expect_failure",
+    "only_on",
+    "skip_on",
+    "requires",
+    "network",
+    "fake_time",
+    "cwd",
+    "sandbox",
+    "fixtures",
+    "env",
+    "env_file",
+    "clean_env",
+    "pass_env",
+    "interpolate",
+    "pty",
+    "interactive",
+    "session",
+    "session_delimiter",
+    "session_prompt",
+    "runner_mode",
+    "input_as",
+    "before_all",
+    "after_all",
+    "serial",
+    "max_lines",
+    "min_lines",
+    "max_bytes",
+    "max_duration",
+    "timeout",
+    "retries",
+    "capture",
+    "compare",
+    "tolerance",
+    "relative_tolerance",
+    "ignore_paths",
+    "strip_ansi",
+    "line_endings",
+    "normalize",
+    "ignore_case",
+    "ignore_trailing_whitespace",
+    "squash_whitespace",
+    "ignore_blank_lines",
+    "mask",
+    "tags",
+    "order",
+    "priority",
+];
+
+/// Rewrites every file into the canonical layout, or with `check` only prints the ones that
+/// are not formatted. Returns the number of files that were (or would be) changed.
+pub fn format_files(files: &[PathBuf], check: bool) -> Result<usize> {
+    let mut changed = 0;
+    for file in files {
+        let src =
+            fs::read_to_string(file).with_context(|| format!("Failed to read file {:?}", file))?;
+        let formatted = format_markdown(&src);
+        if formatted == src {
+            continue;
+        }
+        changed += 1;
+        if check {
+            println!("Not formatted: {}", file.to_string_lossy());
+        } else {
+            fs::write(file, formatted)
+                .with_context(|| format!("Failed to write file {:?}", file))?;
+            println!("Formatted {}", file.to_string_lossy());
+        }
+    }
+    if check {
+        println!("{} of {} files need formatting", changed, files.len());
+    } else {
+        println!("Formatted {} of {} files", changed, files.len());
+    }
+    Ok(changed)
+}
+
+/// Formats a test file: frontmatter keys in canonical order, ATX headings as `## Title`,
+/// lowercase fence languages, single blank lines and exactly one trailing newline.
+/// Code block contents are never touched.
+pub fn format_markdown(src: &str) -> String {
+    let newline = if src.contains("\r\n") { "\r\n" } else { "\n" };
+    let lines: Vec<&str> = src.lines().collect();
+    let mut out: Vec<String> = vec![];
+
+    let mut i = 0;
+    if lines.first().is_some_and(|line| line.trim() == "---")
+        && let Some(end) = lines.iter().skip(1).position(|line| line.trim() == "---")
+    {
+        out.push("---".to_string());
+        out.extend(sort_header_keys(&lines[1..end + 1]));
+        out.push("---".to_string());
+        i = end + 2;
+    }
+
+    // Opening fence character and length while inside a fenced code block
+    let mut fence: Option<(char, usize)> = None;
+    for line in &lines[i..] {
+        if let Some((fence_char, fence_len)) = fence {
+            let trimmed = line.trim_start();
+            let closing = trimmed.chars().take_while(|&c| c == fence_char).count();
+            if closing >= fence_len && trimmed[closing..].trim().is_empty() {
+                fence = None;
+            }
+            out.push(line.to_string());
+        } else if let Some((indent, fence_char, fence_len, info)) = parse_fence(line) {
+            fence = Some((fence_char, fence_len));
+            out.push(format!(
+                "{}{}{}",
+                indent,
+                fence_char.to_string().repeat(fence_len),
+                normalize_info(info)
+            ));
+        } else if let Some(heading) = normalize_heading(line) {
+            out.push(heading);
+        } else if line.trim().is_empty() {
+            if out.last().is_some_and(|last| !last.is_empty()) {
+                out.push(String::new());
+            }
+        } else {
+            out.push(line.to_string());
+        }
+    }
+
+    while out.last().is_some_and(|last| last.is_empty()) {
+        out.pop();
+    }
+    let mut formatted = out.join(newline);
+    formatted.push_str(newline);
+    formatted
+}
+
+/// Reorders the top-level keys of the frontmatter, keeping each key's lines together
+fn sort_header_keys(lines: &[&str]) -> Vec<String> {
+    // Lines before the first key (e.g. comments) stay on top
+    let mut groups: Vec<(usize, Vec<String>)> = vec![(0, vec![])];
+    for line in lines {
+        let is_key = !line.starts_with([' ', '\t', '#', '-']) && line.contains(':');
+        if is_key {
+            let key = line.split(':').next().unwrap_or_default().trim();
+            let rank = HEADER_KEY_ORDER
+                .iter()
+                .position(|k| *k == key)
+                .unwrap_or(HEADER_KEY_ORDER.len());
+            groups.push((rank + 1, vec![]));
+        }
+        if let Some((_, group)) = groups.last_mut() {
+            group.push(line.trim_end().to_string());
+        }
+    }
+    // Stable, so unknown keys keep their order
+    groups.sort_by_key(|(rank, _)| *rank);
+    groups.into_iter().flat_map(|(_, group)| group).collect()
+}
+
+/// Splits an opening code fence into (indentation, fence character, fence length, info string)
+fn parse_fence(line: &str) -> Option<(&str, char, usize, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    let indent = &line[..line.len() - trimmed.len()];
+    let fence_char = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let fence_len = trimmed.chars().take_while(|&c| c == fence_char).count();
+    if indent.len() > 3 || fence_len < 3 {
+        return None;
+    }
+    let info = &trimmed[fence_len..];
+    // Backtick fences cannot have backticks in their info string
+    if fence_char == '`' && info.contains('`') {
+        return None;
+    }
+    Some((indent, fence_char, fence_len, info))
+}
+
+/// Trims the info string and lowercases the language, leaving file names (`main.py`,
+/// `file=Out.txt`) as they are
+fn normalize_info(info: &str) -> String {
+    let mut words = info.split_whitespace();
+    let Some(lang) = words.next() else {
+        return String::new();
+    };
+    let lang = if lang.contains(['.', '=']) {
+        lang.to_string()
+    } else {
+        lang.to_ascii_lowercase()
+    };
+    std::iter::once(lang)
+        .chain(words.map(str::to_string))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Rewrites an ATX heading as `#`s, one space and the title, without indentation or closing `#`s
+fn normalize_heading(line: &str) -> Option<String> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let mut title = rest.trim();
+    // A closing sequence must be preceded by a space to not be part of the title
+    let without_closing = title.trim_end_matches('#');
+    if without_closing.is_empty() || without_closing.ends_with([' ', '\t']) {
+        title = without_closing.trim_end();
+    }
+    let hashes = "#".repeat(level);
+    Some(if title.is_empty() {
+        hashes
+    } else {
+        format!("{} {}", hashes, title)
+    })
+}
@@ -1,67 +1,332 @@
 mod cli;
-mod parser;
-mod runner;
-mod types;
-mod util;
+mod config;
+mod events;
+mod formatter;
+mod merge;
+mod metadata;
+mod report;
+mod reporter;
+mod review;
+mod scaffold;
+mod state;
 
 use anyhow::Result;
-use clap::Parser;
-use cli::Args;
+use cli::{Args, Command};
 use glob::glob;
-use parser::*;
-use rayon::prelude::*;
-use runner::*;
+use marco_core::runner::NOT_RUN_REASON;
+use marco_core::{collect_tests, requirements, run_suite, util, validate};
+use reporter::{Reporters, RunSummary};
+use std::collections::HashMap;
 
-fn main() -> Result<()> {
-    let mut args = Args::parse();
-    args = args.set_defaults();
+/// Exit code when more tests failed than `--max-failures`/`--allow-failures` tolerate
+const TEST_FAILURES_EXIT_CODE: i32 = 1;
+/// Exit code for errors other than the ones below, e.g. a report that could not be written
+/// (matches clap's exit code for invalid arguments)
+const ERROR_EXIT_CODE: i32 = 2;
+/// Exit code when test files could not be found or parsed
+const COLLECTION_ERROR_EXIT_CODE: i32 = 3;
+/// Exit code when no test files or no tests in them were found
+const NO_TESTS_EXIT_CODE: i32 = 4;
+/// Exit code used when `--run-timeout` aborts the run
+const RUN_TIMEOUT_EXIT_CODE: i32 = 124;
+/// Exit code when marco is interrupted with Ctrl-C or terminated
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Prints progress for humans; with a machine-readable `--format` it goes to stderr so stdout stays parseable
+macro_rules! progress {
+    ($args:expr, $($arg:tt)*) => {
+        if $args.machine_output() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+fn main() {
+    let args = match Args::parse_with_config() {
+        Ok(args) => args.set_defaults(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+    };
+    match run(&args) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+    }
+}
+
+/// Runs marco and returns the exit code
+fn run(args: &Args) -> Result<i32> {
+    if let Some(Command::Report(report)) = &args.command {
+        let failed = merge::merge_reports(report, args)?;
+        return Ok(failures_exit_code(failed, args));
+    }
+    if let Some(Command::New(new)) = &args.command {
+        let path = scaffold::new_test_file(new, args)?;
+        println!("Created {}", path.to_string_lossy());
+        return Ok(0);
+    }
+
+    // Runners lead process groups of their own, which a Ctrl-C in the terminal does not reach
+    #[cfg(unix)]
+    ctrlc::set_handler(|| {
+        marco_core::memory::kill_running();
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    })?;
 
     // rayon configuration
     if let Some(n_threads) = args.threads {
         rayon::ThreadPoolBuilder::new()
             .num_threads(n_threads)
             .build_global()?;
-        if args.verbose {
-            println!("Thread pool set to {n_threads} threads.");
+        if args.verbose > 0 {
+            progress!(args, "Thread pool set to {n_threads} threads.");
         }
     }
 
-    let files: Vec<_> = glob(&args.input)?.collect::<Result<_, _>>()?;
-    println!("Found {} markdown files for `{}`", files.len(), &args.input);
+    let reporters = reporter::reporters(args)?;
+
+    events::emit(
+        args,
+        "collection_started",
+        serde_json::json!({ "input": &args.input }),
+    );
+    let files: Vec<_> = match glob(&args.input) {
+        Ok(paths) => match paths.collect::<Result<_, _>>() {
+            Ok(files) => files,
+            Err(e) => return Ok(collection_error(e.into())),
+        },
+        Err(e) => return Ok(collection_error(e.into())),
+    };
+    progress!(
+        args,
+        "Found {} markdown files for `{}`",
+        files.len(),
+        &args.input
+    );
     if files.is_empty() {
-        println!("No test markdown files found for `{}`", &args.input);
-        return Ok(());
-    }
-    let tests = collect_tests(&files)?;
-    if tests.is_empty() {
-        println!("No tests found in markdown files for `{}`", &args.input);
-        return Ok(());
-    }
-    println!("Found {} tests in {} files.", tests.len(), files.len());
-
-    let results: Vec<_> = tests
-        .par_iter()
-        .map(|test| run_test_case(test, args.runner.clone()))
-        .collect();
-
-    let passed = results.iter().filter(|r| r.passed).count();
-    println!("\nResults: {} passed / {} total", passed, results.len());
-    for res in &results {
-        if res.passed {
-            println!(
-                "\x1b[92m✔\x1b[0m {} \x1b[90m(in {:?})\x1b[0m",
-                res.name, res.file
+        progress!(args, "No test markdown files found for `{}`", &args.input);
+        report_empty_run(&reporters, 0, args)?;
+        return Ok(NO_TESTS_EXIT_CODE);
+    }
+    if let Some(Command::Fmt(fmt)) = &args.command {
+        let changed = formatter::format_files(&files, fmt.check)?;
+        return Ok(if fmt.check && changed > 0 {
+            TEST_FAILURES_EXIT_CODE
+        } else {
+            0
+        });
+    }
+    if let Some(Command::Validate) = args.command {
+        let problems = validate::validate_files(&files)?;
+        return Ok(if problems > 0 {
+            COLLECTION_ERROR_EXIT_CODE
+        } else {
+            0
+        });
+    }
+    let mut tests = match collect_tests(&files) {
+        Ok(tests) => tests,
+        Err(e) => return Ok(collection_error(e)),
+    };
+    let collected = tests.len();
+    tests.retain(|test| args.selects(test));
+    let mut state = state::State::load().unwrap_or_else(|e| {
+        eprintln!("Warning: {:#}", e);
+        state::State::default()
+    });
+    if args.last_failed {
+        if state.failed.is_empty() {
+            progress!(
+                args,
+                "No failures recorded by earlier runs; running all tests"
             );
         } else {
-            println!(
-                "\x1b[91m✘\x1b[0m {} \x1b[90m(in {:?})\x1b[0m",
-                res.name, res.file
+            tests.retain(|test| state.failed.contains(&report::TestId::of_test(test)));
+        }
+    }
+    let filtered_out = collected - tests.len();
+    // Reordering keeps the files in collection order and `order` first within each, like
+    // collection does
+    let file_index: HashMap<_, _> = files.iter().enumerate().map(|(i, f)| (f, i)).collect();
+    if let Some(seed) = args.shuffle_seed() {
+        tests.sort_by_key(|t| {
+            (
+                file_index.get(&t.file),
+                t.header.order,
+                util::test_seed(seed, &t.file, &t.header.name),
+            )
+        });
+    }
+    if args.failed_first {
+        // Stable, so failures only move ahead of the other tests with their file and order
+        tests.sort_by_key(|test| {
+            (
+                file_index.get(&test.file),
+                test.header.order,
+                !state.failed.contains(&report::TestId::of_test(test)),
+            )
+        });
+    }
+    // Every shard's JSON report lists all selected tests, so `marco report` notices a missing shard
+    let collected_ids = report::collected_ids(&tests);
+    let mut other_shards = 0;
+    if let Some(shard) = args.shard {
+        tests.retain(|test| shard.contains(test));
+        other_shards = collected_ids.len() - tests.len();
+    }
+    if args.dry_run || matches!(args.command, Some(Command::List(_))) {
+        let json = matches!(&args.command, Some(Command::List(list)) if list.json);
+        report::print_test_list(&tests, json, args);
+        return Ok(if tests.is_empty() && other_shards == 0 {
+            NO_TESTS_EXIT_CODE
+        } else {
+            0
+        });
+    }
+    requirements::check_requirements(&mut tests);
+    if tests.is_empty() && other_shards == 0 {
+        if filtered_out > 0 {
+            progress!(
+                args,
+                "No tests match the --filter/--exact/--tag/--skip-tag/--last-failed selection ({} filtered out)",
+                filtered_out
+            );
+        } else {
+            progress!(
+                args,
+                "No tests found in markdown files for `{}`",
+                &args.input
             );
-            if let Some(err) = &res.error {
-                println!("    Error: {}", err);
-            }
-            util::print_diff(&res.actual, &res.expected);
         }
+        report_empty_run(&reporters, filtered_out, args)?;
+        return Ok(NO_TESTS_EXIT_CODE);
+    }
+    progress!(
+        args,
+        "Found {} tests in {} files.",
+        tests.len(),
+        files.len()
+    );
+    if filtered_out > 0 {
+        progress!(
+            args,
+            "{} tests filtered out by --filter/--exact/--tag/--skip-tag/--last-failed",
+            filtered_out
+        );
+    }
+    if let Some(shard) = args.shard {
+        progress!(
+            args,
+            "{} tests belong to other shards than {} (--shard)",
+            other_shards,
+            shard
+        );
+    }
+    events::emit(
+        args,
+        "collection_finished",
+        serde_json::json!({ "files": files.len(), "tests": tests.len() }),
+    );
+    progress!(
+        args,
+        "Using seed {} (--seed to reproduce)",
+        args.seed.unwrap_or_default()
+    );
+    if let Some(seed) = args.shuffle_seed() {
+        progress!(
+            args,
+            "Shuffled the test order with seed {} (--shuffle={} to reproduce)",
+            seed,
+            seed
+        );
+    }
+    for test in tests.iter().filter(|t| t.has_empty_expected()) {
+        eprintln!(
+            "Warning: test {:?} in {:?} has an empty Expected Output block",
+            test.header.name, test.file
+        );
+    }
+
+    let metadata = metadata::RunMetadata::collect(&tests, args);
+    reporters.run_started(&tests, &metadata);
+
+    let suite = run_suite(&tests, &args.run, &reporters);
+    let mut results = suite.results;
+
+    if args.interactive {
+        review::review_failures(&tests, &mut results, args)?;
+    } else if let Some(Command::Update) = args.command {
+        review::update_failures(&tests, &mut results, args)?;
+    }
+    for (file, error) in &suite.after_all_errors {
+        eprintln!("Error: after_all of {:?} failed: {}", file, error);
+        events::emit(
+            args,
+            "after_all_failed",
+            serde_json::json!({ "file": file, "error": error }),
+        );
+    }
+    // After the review, so the reports count accepted tests as passed, like the exit code
+    reporters.run_finished(&RunSummary {
+        results: &results,
+        filtered_out,
+        collected: &collected_ids,
+        metadata: &metadata,
+        first_failure: suite.first_failure,
+    })?;
+    state.update(&results);
+    if let Err(e) = state.save() {
+        eprintln!("Warning: {:#}", e);
+    }
+
+    if args.run.deadline_exceeded() {
+        let not_run = results
+            .iter()
+            .filter(|r| r.skipped.as_deref() == Some(NOT_RUN_REASON))
+            .count();
+        eprintln!(
+            "Run aborted: deadline of {:?} exceeded (--run-timeout); {} tests were not run",
+            args.run_timeout.unwrap_or_default(),
+            not_run
+        );
+        return Ok(RUN_TIMEOUT_EXIT_CODE);
+    }
+    // A failed `after_all` fails the run like a failed test
+    let failed = results.iter().filter(|r| r.failed()).count() + suite.after_all_errors.len();
+    Ok(failures_exit_code(failed, args))
+}
+
+/// Lets every reporter finish a run in which no tests ran, e.g. because none were found
+fn report_empty_run(reporters: &Reporters, filtered_out: usize, args: &Args) -> Result<()> {
+    let metadata = metadata::RunMetadata::collect(&[], args);
+    reporters.run_started(&[], &metadata);
+    reporters.run_finished(&RunSummary {
+        results: &[],
+        filtered_out,
+        collected: &[],
+        metadata: &metadata,
+        first_failure: None,
+    })
+}
+
+/// Reports an error finding or parsing the test files
+fn collection_error(e: anyhow::Error) -> i32 {
+    eprintln!("Error: {:?}", e);
+    COLLECTION_ERROR_EXIT_CODE
+}
+
+/// Exit code for a run with `failed` failed tests, honoring `--max-failures` and `--allow-failures`
+fn failures_exit_code(failed: usize, args: &Args) -> i32 {
+    let tolerated = args.allow_failures || args.max_failures.is_some_and(|max| failed <= max);
+    if failed == 0 || tolerated {
+        0
+    } else {
+        TEST_FAILURES_EXIT_CODE
     }
-    Ok(())
 }
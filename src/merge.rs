@@ -0,0 +1,80 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Result, bail};
+
+use crate::cli::{Args, ReportArgs};
+use crate::report::{self, JsonReport, TestId};
+use crate::reporter::{self, Reporter, RunSummary};
+
+/// Combines JSON reports into one, failing if a test has no result or more than one.
+/// The merged results are then written to every report requested on the command line.
+/// Returns the number of failed tests.
+pub fn merge_reports(merge: &ReportArgs, args: &Args) -> Result<usize> {
+    let mut reports = merge
+        .reports
+        .iter()
+        .map(|path| JsonReport::read(path))
+        .collect::<Result<Vec<_>>>()?;
+    // Shards usually run on different machines; the first report's environment stands for all
+    let metadata = reports[0].metadata.clone();
+
+    let mut collected = BTreeSet::new();
+    let mut seen = BTreeSet::new();
+    let mut duplicates = vec![];
+    let mut results = vec![];
+    for report in &mut reports {
+        collected.extend(report.collected.drain(..));
+        for res in report.results.drain(..) {
+            let id = TestId::of(&res);
+            if !seen.insert(id.clone()) {
+                duplicates.push(id);
+                continue;
+            }
+            results.push(res);
+        }
+    }
+    let missing: Vec<_> = collected.difference(&seen).collect();
+
+    let mut problems = vec![];
+    for id in &duplicates {
+        problems.push(format!(
+            "duplicate result for {:?} (in {:?})",
+            id.name, id.file
+        ));
+    }
+    for id in &missing {
+        problems.push(format!("no result for {:?} (in {:?})", id.name, id.file));
+    }
+    if !problems.is_empty() {
+        bail!(
+            "Cannot merge {} reports:\n  {}",
+            merge.reports.len(),
+            problems.join("\n  ")
+        );
+    }
+
+    let passed = results.iter().filter(|r| r.passed).count();
+    println!(
+        "Merged {} reports\nResults: {} passed / {} total",
+        merge.reports.len(),
+        passed,
+        results.len()
+    );
+    report::print_file_summary(&results);
+
+    let collected: Vec<_> = collected.into_iter().collect();
+    if let Some(path) = &merge.output {
+        report::write_json(path, &report::json_report(&collected, &results, &metadata)?)?;
+    }
+    let run = RunSummary {
+        results: &results,
+        filtered_out: 0,
+        collected: &collected,
+        metadata: &metadata,
+        first_failure: None,
+    };
+    for reporter in reporter::file_reporters(args) {
+        reporter.run_finished(&run)?;
+    }
+    Ok(results.iter().filter(|r| r.failed()).count())
+}
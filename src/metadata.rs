@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use marco_core::runner::{resolve_runner, runner_version};
+use marco_core::types::MarcoTestCase;
+use marco_core::util::parse_shell_cmd;
+
+use crate::cli::Args;
+
+/// Environment variables worth recording alongside results, besides all `MARCO_*` ones
+const RECORDED_ENV_VARS: &[&str] = &["CI", "LANG", "LC_ALL", "TZ", "SHELL"];
+
+/// Describes the environment a run happened in, embedded into every report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub entries: Vec<(String, String)>,
+}
+
+impl RunMetadata {
+    /// Fingerprints the machine, marco itself and the runners the tests will use
+    pub fn collect(tests: &[MarcoTestCase], args: &Args) -> Self {
+        let mut entries = vec![
+            (
+                "marco.version".to_owned(),
+                env!("CARGO_PKG_VERSION").to_owned(),
+            ),
+            ("os".to_owned(), env::consts::OS.to_owned()),
+            ("arch".to_owned(), env::consts::ARCH.to_owned()),
+        ];
+        if let Some(host) = hostname() {
+            entries.push(("hostname".to_owned(), host));
+        }
+
+        entries.extend(git_metadata());
+
+        // Each program is probed once, in the directory and environment of its first test
+        let mut commands: BTreeMap<String, (&MarcoTestCase, BTreeSet<&str>)> = BTreeMap::new();
+        for test in tests {
+            for (cmd, _) in resolve_runner(test, &args.run).into_iter().flatten() {
+                if let Some((prog, _)) = parse_shell_cmd(cmd) {
+                    commands
+                        .entry(prog)
+                        .or_insert((test, BTreeSet::new()))
+                        .1
+                        .insert(cmd);
+                }
+            }
+        }
+        for (prog, (test, cmds)) in commands {
+            let cmds: Vec<&str> = cmds.into_iter().collect();
+            entries.push((format!("runner.{}", prog), cmds.join("; ")));
+            if let Some(version) = runner_version(&prog, test, &args.run) {
+                entries.push((format!("runner.{}.version", prog), version));
+            }
+        }
+
+        let mut vars: Vec<(String, String)> = env::vars()
+            .filter(|(key, _)| {
+                key.starts_with("MARCO_") || RECORDED_ENV_VARS.contains(&key.as_str())
+            })
+            .map(|(key, value)| (format!("env.{}", key), value))
+            .collect();
+        vars.sort();
+        entries.extend(vars);
+
+        RunMetadata { entries }
+    }
+}
+
+/// Commit, branch and dirty state of the git repository marco runs in, if any
+fn git_metadata() -> Vec<(String, String)> {
+    let Some(commit) = git(&["rev-parse", "HEAD"]) else {
+        return vec![];
+    };
+    let mut entries = vec![("git.commit".to_owned(), commit)];
+    if let Some(branch) = git(&["rev-parse", "--abbrev-ref", "HEAD"]) {
+        entries.push(("git.branch".to_owned(), branch));
+    }
+    if let Some(status) = git(&["status", "--porcelain"]) {
+        let dirty = !status.is_empty();
+        entries.push(("git.dirty".to_owned(), dirty.to_string()));
+    }
+    entries
+}
+
+/// Runs a git command in the current directory, returning its trimmed stdout on success
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Name of this machine, from the environment or the `hostname` command
+fn hostname() -> Option<String> {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| {
+            let output = Command::new("hostname").output().ok()?;
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        })
+        .filter(|host| !host.is_empty())
+}
@@ -0,0 +1,1041 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use marco_core::runner::resolve_runner;
+use marco_core::types::{MarcoTestCase, TestResult};
+use marco_core::util::{format_bytes, paint, print_diff, unified_diff};
+
+use crate::cli::{Args, OutputFormat};
+use crate::metadata::RunMetadata;
+
+/// Prints the results of a run to stdout in the format chosen with `--format`.
+/// `filtered_out` is the number of tests not run because of `--filter`/`--exact`, and
+/// `elapsed` how long the run took.
+pub fn print_results(results: &[TestResult], filtered_out: usize, elapsed: Duration, args: &Args) {
+    match args.format {
+        OutputFormat::Human => print_human(results, filtered_out, args),
+        OutputFormat::Porcelain => print_porcelain(results),
+        OutputFormat::Tap => print_tap(results, args),
+        // Already streamed by `print_test_started`/`print_test_finished`
+        OutputFormat::Teamcity => {}
+        OutputFormat::LibtestJson => print_libtest_suite_finished(results, filtered_out, elapsed),
+    }
+}
+
+/// Announces the run once its tests are collected, for formats that report live progress
+pub fn print_run_started(test_count: usize, args: &Args) {
+    if args.format == OutputFormat::LibtestJson {
+        println!(
+            "{}",
+            serde_json::json!({ "type": "suite", "event": "started", "test_count": test_count })
+        );
+    }
+}
+
+/// Announces a test as it starts, for formats that report live progress.
+/// `id` tells concurrently running tests apart.
+pub fn print_test_started(test: &MarcoTestCase, id: usize, args: &Args) {
+    match args.format {
+        OutputFormat::Teamcity => println!(
+            "##teamcity[testStarted name='{}' flowId='{}']",
+            teamcity_escape(&test.header.name),
+            id
+        ),
+        OutputFormat::LibtestJson => println!(
+            "{}",
+            serde_json::json!({
+                "type": "test",
+                "event": "started",
+                "name": libtest_name(&test.file, &test.header.name),
+            })
+        ),
+        _ => {}
+    }
+}
+
+/// Reports a test's outcome as soon as it finishes, for formats that report live progress
+pub fn print_test_finished(res: &TestResult, id: usize, args: &Args) {
+    match args.format {
+        OutputFormat::Teamcity => print_teamcity_finished(res, id, args),
+        OutputFormat::LibtestJson => print_libtest_finished(res, args),
+        _ => {}
+    }
+}
+
+/// Name of a test in libtest events; tests of different files can share a name, so the file
+/// comes first like a module path
+fn libtest_name(file: &Path, name: &str) -> String {
+    format!("{}::{}", file.to_string_lossy().replace('\\', "/"), name)
+}
+
+/// Prints the libtest event for a finished test. As with a failed Rust test, the error
+/// message and the diff go into the event's `stdout`.
+fn print_libtest_finished(res: &TestResult, args: &Args) {
+    let name = libtest_name(&res.file, &res.name);
+    let exec_time = res.duration.as_secs_f64();
+    let event = if let Some(reason) = &res.skipped {
+        serde_json::json!({ "type": "test", "event": "ignored", "name": name, "message": reason })
+    } else if res.passed {
+        serde_json::json!({ "type": "test", "event": "ok", "name": name, "exec_time": exec_time })
+    } else {
+        let mut stdout = res.error.as_deref().unwrap_or("Test failed").to_string();
+        if res.actual != res.expected {
+            let diff = unified_diff(&res.actual, &res.expected, args.diff_algorithm.into());
+            stdout = format!("{}\n{}", stdout, diff);
+        }
+        serde_json::json!({
+            "type": "test",
+            "event": "failed",
+            "name": name,
+            "exec_time": exec_time,
+            "stdout": stdout,
+        })
+    };
+    println!("{}", event);
+}
+
+/// Prints libtest's closing suite event with the result counts
+fn print_libtest_suite_finished(results: &[TestResult], filtered_out: usize, elapsed: Duration) {
+    let failed = results.iter().filter(|r| r.failed()).count();
+    println!(
+        "{}",
+        serde_json::json!({
+            "type": "suite",
+            "event": if failed == 0 { "ok" } else { "failed" },
+            "passed": results.iter().filter(|r| r.passed).count(),
+            "failed": failed,
+            "ignored": results.iter().filter(|r| r.skipped.is_some()).count(),
+            "measured": 0,
+            "filtered_out": filtered_out,
+            "exec_time": elapsed.as_secs_f64(),
+        })
+    );
+}
+
+/// Prints the TeamCity service messages for a finished test
+fn print_teamcity_finished(res: &TestResult, id: usize, args: &Args) {
+    let name = teamcity_escape(&res.name);
+    if !res.stdout.is_empty() {
+        println!(
+            "##teamcity[testStdOut name='{}' out='{}' flowId='{}']",
+            name,
+            teamcity_escape(&res.stdout),
+            id
+        );
+    }
+    if !res.stderr.is_empty() {
+        println!(
+            "##teamcity[testStdErr name='{}' out='{}' flowId='{}']",
+            name,
+            teamcity_escape(&res.stderr),
+            id
+        );
+    }
+    if let Some(reason) = &res.skipped {
+        println!(
+            "##teamcity[testIgnored name='{}' message='{}' flowId='{}']",
+            name,
+            teamcity_escape(reason),
+            id
+        );
+    } else if !res.passed {
+        let message = res.error.as_deref().unwrap_or("Test failed");
+        let diff = unified_diff(&res.actual, &res.expected, args.diff_algorithm.into());
+        println!(
+            "##teamcity[testFailed name='{}' message='{}' details='{}' flowId='{}']",
+            name,
+            teamcity_escape(message),
+            teamcity_escape(&diff),
+            id
+        );
+    }
+    println!(
+        "##teamcity[testFinished name='{}' duration='{}' flowId='{}']",
+        name,
+        res.duration.as_millis(),
+        id
+    );
+}
+
+/// Escapes a value for a TeamCity service message attribute
+fn teamcity_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '|' => out.push_str("||"),
+            '\'' => out.push_str("|'"),
+            '\n' => out.push_str("|n"),
+            '\r' => out.push_str("|r"),
+            '[' => out.push_str("|["),
+            ']' => out.push_str("|]"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Prints the result counts, every test result (unless `--summary-only`) and the per-file summary
+fn print_human(results: &[TestResult], filtered_out: usize, args: &Args) {
+    let passed = results.iter().filter(|r| r.passed).count();
+    if filtered_out > 0 {
+        println!(
+            "\nResults: {} passed / {} total ({} filtered out)",
+            passed,
+            results.len(),
+            filtered_out
+        );
+    } else {
+        println!("\nResults: {} passed / {} total", passed, results.len());
+    }
+    let slow = results.iter().filter(|r| r.slow).count();
+    if slow > 0 {
+        println!("{} tests exceeded the slow threshold", slow);
+    }
+    let too_slow = results.iter().filter(|r| r.too_slow).count();
+    if too_slow > 0 {
+        println!("{} tests failed for exceeding max_duration", too_slow);
+    }
+    let flaky = results.iter().filter(|r| r.flaky).count();
+    if flaky > 0 {
+        println!("{} flaky tests passed only after a retry", flaky);
+    }
+    if !args.summary_only {
+        for res in results {
+            print_result(res, args);
+        }
+    }
+    print_file_summary(results);
+}
+
+/// Prints every collected test with its location, runner and tags, or a JSON array of them
+pub fn print_test_list(tests: &[MarcoTestCase], json: bool, args: &Args) {
+    if json {
+        let list: Vec<_> = tests
+            .iter()
+            .map(|test| {
+                let runner: Option<Vec<&str>> = resolve_runner(test, &args.run)
+                    .map(|steps| steps.into_iter().map(|(cmd, _)| cmd).collect());
+                serde_json::json!({
+                    "name": &test.header.name,
+                    "file": &test.file,
+                    "line": test.block_start_line,
+                    "runner": runner,
+                    "tags": &test.header.tags,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&list).unwrap_or_default()
+        );
+        return;
+    }
+    for test in tests {
+        let runner = resolve_runner(test, &args.run)
+            .map(|steps| {
+                steps
+                    .into_iter()
+                    .map(|(cmd, _)| cmd)
+                    .collect::<Vec<_>>()
+                    .join(" && ")
+            })
+            .unwrap_or_else(|| "(no runner)".to_string());
+        let mut line = format!(
+            "{} \x1b[90m({}:{}, runner: {}",
+            test.header.name,
+            test.file.to_string_lossy(),
+            test.block_start_line,
+            runner
+        );
+        if !test.header.tags.is_empty() {
+            line.push_str(&format!(", tags: {}", test.header.tags.join(", ")));
+        }
+        line.push_str(")\x1b[0m");
+        println!("{}", paint(&line));
+    }
+    match args.shard {
+        // Shards depend only on file paths and names, so every machine lists the same split
+        Some(shard) => println!(
+            "\n{} tests in shard {} (split by a stable hash of each test's file path and name)",
+            tests.len(),
+            shard
+        ),
+        None => println!("\n{} tests", tests.len()),
+    }
+}
+
+/// Prints a single test result to the console
+pub fn print_result(res: &TestResult, args: &Args) {
+    if let Some(reason) = &res.skipped {
+        println!(
+            "{}",
+            paint(&format!(
+                "\x1b[93m○\x1b[0m {} \x1b[90m(in {:?}, skipped: {})\x1b[0m",
+                res.name, res.file, reason
+            ))
+        );
+    } else if res.passed {
+        println!(
+            "{}",
+            paint(&format!(
+                "\x1b[92m✔\x1b[0m {} \x1b[90m(in {:?})\x1b[0m{}{}",
+                res.name,
+                res.file,
+                slow_marker(res),
+                flaky_marker(res)
+            ))
+        );
+        for warning in &res.warnings {
+            println!(
+                "{}",
+                paint(&format!("    \x1b[93mWarning:\x1b[0m {}", warning))
+            );
+        }
+        if args.verbose > 0
+            && let Some(peak) = res.peak_memory
+        {
+            println!(
+                "{}",
+                paint(&format!(
+                    "    \x1b[90mPeak memory: {}\x1b[0m",
+                    format_bytes(peak)
+                ))
+            );
+        }
+        if args.verbose > 0 && !res.tags.is_empty() {
+            println!(
+                "{}",
+                paint(&format!("    \x1b[90mTags: {}\x1b[0m", res.tags.join(", ")))
+            );
+        }
+    } else {
+        println!(
+            "{}",
+            paint(&format!(
+                "\x1b[91m✘\x1b[0m {} \x1b[90m(in {:?})\x1b[0m{}",
+                res.name,
+                res.file,
+                slow_marker(res)
+            ))
+        );
+        if let Some(err) = &res.error {
+            println!("    Error: {}", err);
+        }
+        if let Some(code) = res.exit_code {
+            println!("    Exit code: {}", code);
+        }
+        if !res.attempts.is_empty() {
+            println!("    Attempts: {} (all failed)", res.attempts.len());
+        }
+        if let Some(seed) = res.seed {
+            println!("    Seed: {}", seed);
+        }
+        if let Some(repro) = &res.repro {
+            println!("    Reproduce: {}", repro);
+        }
+        if let Some(peak) = res.peak_memory {
+            println!("    Peak memory: {}", format_bytes(peak));
+        }
+        if let Some(dir) = &res.artifacts {
+            println!("    Artifacts: {:?}", dir);
+        }
+        if args.verbose > 0 && !res.tags.is_empty() {
+            println!("    Tags: {}", res.tags.join(", "));
+        }
+        print_diff(&res.actual, &res.expected, args.diff_algorithm.into());
+    }
+}
+
+/// Returns a colored " too slow"/" slow (1.23s)" suffix for tests over max_duration or the slow threshold
+fn slow_marker(res: &TestResult) -> String {
+    if res.too_slow {
+        format!(
+            " \x1b[91mtoo slow ({:.2}s)\x1b[0m",
+            res.duration.as_secs_f64()
+        )
+    } else if res.slow {
+        format!(" \x1b[93mslow ({:.2}s)\x1b[0m", res.duration.as_secs_f64())
+    } else {
+        String::new()
+    }
+}
+
+/// Returns a colored " flaky (passed on attempt 2)" suffix for tests that passed on a retry
+fn flaky_marker(res: &TestResult) -> String {
+    if res.flaky {
+        format!(
+            " \x1b[93mflaky (passed on attempt {})\x1b[0m",
+            res.attempts.len()
+        )
+    } else {
+        String::new()
+    }
+}
+
+/// Prints the environment fingerprint of the run
+pub fn print_metadata(metadata: &RunMetadata) {
+    println!("Environment:");
+    for (key, value) in &metadata.entries {
+        println!("{}", paint(&format!("  \x1b[90m{}:\x1b[0m {}", key, value)));
+    }
+}
+
+/// Version of the `--porcelain` format, bumped only if a record ever has to change incompatibly
+const PORCELAIN_VERSION: u32 = 1;
+
+/// Prints results in the `--porcelain` format (see README): a version record, one
+/// `result` record per test and a final `summary` record, with tab-separated fields
+fn print_porcelain(results: &[TestResult]) {
+    println!("version\t{}", PORCELAIN_VERSION);
+    for res in results {
+        println!(
+            "result\t{}\t{}\t{}\t{}",
+            res.status(),
+            res.duration.as_millis(),
+            porcelain_escape(&res.file.to_string_lossy()),
+            porcelain_escape(&res.name)
+        );
+    }
+    let passed = results.iter().filter(|r| r.passed).count();
+    let failed = results.iter().filter(|r| r.failed()).count();
+    let skipped = results.iter().filter(|r| r.skipped.is_some()).count();
+    let flaky = results.iter().filter(|r| r.flaky).count();
+    println!(
+        "summary\t{}\t{}\t{}\t{}\t{}",
+        passed,
+        failed,
+        skipped,
+        results.len(),
+        flaky
+    );
+}
+
+/// Prints results as TAP version 13, with a YAML diagnostics block for every failure
+fn print_tap(results: &[TestResult], args: &Args) {
+    println!("TAP version 13");
+    println!("1..{}", results.len());
+    for (i, res) in results.iter().enumerate() {
+        // `#` starts a directive in TAP, so it must not appear unescaped in the description
+        let description = format!("{} (in {})", res.name, res.file.to_string_lossy())
+            .replace('\\', "\\\\")
+            .replace('#', "\\#")
+            .replace(['\r', '\n'], " ");
+        if let Some(reason) = &res.skipped {
+            println!("ok {} - {} # SKIP {}", i + 1, description, reason);
+            continue;
+        }
+        if res.passed {
+            println!("ok {} - {}", i + 1, description);
+            if res.flaky {
+                println!("# flaky: passed on attempt {}", res.attempts.len());
+            }
+            continue;
+        }
+        println!("not ok {} - {}", i + 1, description);
+        println!("  ---");
+        let message = res.error.as_deref().unwrap_or("Test failed");
+        println!("  message: {}", yaml_string(message));
+        println!(
+            "  severity: {}",
+            if res.too_slow { "too_slow" } else { "fail" }
+        );
+        println!("  file: {}", yaml_string(&res.file.to_string_lossy()));
+        println!("  duration_ms: {}", res.duration.as_millis());
+        if let Some(code) = res.exit_code {
+            println!("  exit_code: {}", code);
+        }
+        if !res.attempts.is_empty() {
+            println!("  attempts: {}", res.attempts.len());
+        }
+        if let Some(seed) = res.seed {
+            println!("  seed: {}", seed);
+        }
+        if let Some(repro) = &res.repro {
+            println!("  repro: {}", yaml_string(repro));
+        }
+        let diff = unified_diff(&res.actual, &res.expected, args.diff_algorithm.into());
+        if !diff.is_empty() {
+            println!("  diff: |");
+            for line in diff.lines() {
+                println!("    {}", line);
+            }
+        }
+        println!("  ...");
+    }
+}
+
+/// Quotes a string for YAML; JSON strings are valid YAML scalars
+fn yaml_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+/// Escapes backslashes, tabs and line breaks so every porcelain record stays on one line
+fn porcelain_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Prints a per-file breakdown of test outcomes and durations
+pub fn print_file_summary(results: &[TestResult]) {
+    let mut by_file: BTreeMap<&PathBuf, Vec<&TestResult>> = BTreeMap::new();
+    for res in results {
+        by_file.entry(&res.file).or_default().push(res);
+    }
+
+    let width = by_file
+        .keys()
+        .map(|f| f.to_string_lossy().len())
+        .max()
+        .unwrap_or(0)
+        .max("File".len());
+    println!(
+        "\n{:<width$}  {:>5}  {:>6}  {:>6}  {:>7}  {:>9}  {:>9}",
+        "File", "Tests", "Passed", "Failed", "Skipped", "Duration", "Peak mem"
+    );
+    for (file, tests) in by_file {
+        let passed = tests.iter().filter(|r| r.passed).count();
+        let failed = tests.iter().filter(|r| r.failed()).count();
+        let skipped = tests.iter().filter(|r| r.skipped.is_some()).count();
+        let duration: Duration = tests.iter().map(|r| r.duration).sum();
+        let peak = tests
+            .iter()
+            .filter_map(|r| r.peak_memory)
+            .max()
+            .map(format_bytes)
+            .unwrap_or_else(|| "-".to_owned());
+        println!(
+            "{:<width$}  {:>5}  {:>6}  {:>6}  {:>7}  {:>8.2}s  {:>9}",
+            file.to_string_lossy(),
+            tests.len(),
+            passed,
+            failed,
+            skipped,
+            duration.as_secs_f64(),
+            peak
+        );
+    }
+}
+
+/// Writes an uncolored transcript of the run, including full child output, to `path`.
+/// Child output of passing tests is only included at verbosity 2 (`-vv`) and above.
+pub fn write_log(
+    path: &Path,
+    results: &[TestResult],
+    metadata: &RunMetadata,
+    args: &Args,
+) -> Result<()> {
+    let mut log = String::new();
+    let passed = results.iter().filter(|r| r.passed).count();
+    writeln!(log, "Results: {} passed / {} total", passed, results.len())?;
+    writeln!(log, "Environment:")?;
+    for (key, value) in &metadata.entries {
+        writeln!(log, "  {}: {}", key, value)?;
+    }
+
+    for res in results {
+        let status = if res.skipped.is_some() {
+            "SKIP"
+        } else if res.too_slow {
+            "SLOW"
+        } else if res.passed {
+            "PASS"
+        } else {
+            "FAIL"
+        };
+        writeln!(log, "\n[{}] {} (in {:?})", status, res.name, res.file)?;
+        if res.slow {
+            writeln!(log, "Slow: took {:.2}s", res.duration.as_secs_f64())?;
+        }
+        if res.flaky {
+            writeln!(log, "Flaky: passed on attempt {}", res.attempts.len())?;
+        }
+        for (i, attempt) in res.attempts.iter().enumerate().filter(|(_, a)| !a.passed) {
+            writeln!(
+                log,
+                "Attempt {} failed after {:.2}s: {}",
+                i + 1,
+                attempt.duration.as_secs_f64(),
+                attempt.error.as_deref().unwrap_or("output mismatch")
+            )?;
+        }
+        if let Some(peak) = res.peak_memory {
+            writeln!(log, "Peak memory: {}", format_bytes(peak))?;
+        }
+        for warning in &res.warnings {
+            writeln!(log, "Warning: {}", warning)?;
+        }
+        if let Some(err) = &res.error {
+            writeln!(log, "Error: {}", err)?;
+        }
+        if let Some(code) = res.exit_code {
+            writeln!(log, "Exit code: {}", code)?;
+        }
+        if let Some(seed) = res.seed {
+            writeln!(log, "Seed: {}", seed)?;
+        }
+        if let Some(repro) = &res.repro {
+            writeln!(log, "Reproduce: {}", repro)?;
+        }
+        if let Some(reason) = &res.skipped {
+            writeln!(log, "Skipped: {}", reason)?;
+        }
+        if let Some(dir) = &res.artifacts {
+            writeln!(log, "Artifacts: {:?}", dir)?;
+        }
+        if res.failed() {
+            writeln!(log, "--- diff")?;
+            write!(
+                log,
+                "{}",
+                unified_diff(&res.actual, &res.expected, args.diff_algorithm.into())
+            )?;
+        }
+        if res.failed() || (res.passed && args.verbose >= 2) {
+            writeln!(log, "--- stdout")?;
+            writeln!(log, "{}", res.stdout)?;
+            writeln!(log, "--- stderr")?;
+            writeln!(log, "{}", res.stderr)?;
+        }
+    }
+
+    fs::write(path, log).with_context(|| format!("Failed to write log file {:?}", path))
+}
+
+/// Writes a JUnit XML report to `path`, with one testsuite per markdown file
+pub fn write_junit(
+    path: &Path,
+    results: &[TestResult],
+    metadata: &RunMetadata,
+    args: &Args,
+) -> Result<()> {
+    let mut by_file: BTreeMap<&PathBuf, Vec<&TestResult>> = BTreeMap::new();
+    for res in results {
+        by_file.entry(&res.file).or_default().push(res);
+    }
+
+    let failures = results.iter().filter(|r| r.failed()).count();
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        xml,
+        r#"<testsuites name="marco" tests="{}" failures="{}">"#,
+        results.len(),
+        failures
+    )?;
+    for (file, tests) in by_file {
+        let suite = xml_escape(&file.to_string_lossy());
+        writeln!(
+            xml,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}">"#,
+            suite,
+            tests.len(),
+            tests.iter().filter(|r| r.failed()).count(),
+            tests.iter().filter(|r| r.skipped.is_some()).count()
+        )?;
+        writeln!(xml, "    <properties>")?;
+        for (key, value) in &metadata.entries {
+            writeln!(
+                xml,
+                r#"      <property name="{}" value="{}"/>"#,
+                xml_escape(key),
+                xml_escape(value)
+            )?;
+        }
+        writeln!(xml, "    </properties>")?;
+        for res in tests {
+            writeln!(
+                xml,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}">"#,
+                xml_escape(&res.name),
+                suite,
+                res.duration.as_secs_f64()
+            )?;
+            if res.slow || res.flaky || res.peak_memory.is_some() || res.seed.is_some() {
+                writeln!(xml, "      <properties>")?;
+                if res.slow {
+                    writeln!(xml, r#"        <property name="slow" value="true"/>"#)?;
+                }
+                if res.flaky {
+                    writeln!(xml, r#"        <property name="flaky" value="true"/>"#)?;
+                }
+                if let Some(peak) = res.peak_memory {
+                    writeln!(
+                        xml,
+                        r#"        <property name="peak_memory_bytes" value="{}"/>"#,
+                        peak
+                    )?;
+                }
+                if let Some(seed) = res.seed {
+                    writeln!(xml, r#"        <property name="seed" value="{}"/>"#, seed)?;
+                }
+                writeln!(xml, "      </properties>")?;
+            }
+            if let Some(reason) = &res.skipped {
+                writeln!(xml, r#"      <skipped message="{}"/>"#, xml_escape(reason))?;
+            } else if !res.passed {
+                let message = res.error.as_deref().unwrap_or("Test failed");
+                let diff = unified_diff(&res.actual, &res.expected, args.diff_algorithm.into());
+                let kind = if res.too_slow { "too_slow" } else { "failure" };
+                writeln!(
+                    xml,
+                    r#"      <failure message="{}" type="{}">{}</failure>"#,
+                    xml_escape(message),
+                    kind,
+                    xml_escape(&diff)
+                )?;
+            }
+            // Earlier failed attempts, in the elements Surefire uses for reruns
+            if res.skipped.is_none() {
+                let element = if res.passed {
+                    "flakyFailure"
+                } else {
+                    "rerunFailure"
+                };
+                let last = res.attempts.len().saturating_sub(1);
+                for attempt in res.attempts.iter().take(last) {
+                    writeln!(
+                        xml,
+                        r#"      <{} message="{}" type="failure" time="{:.3}"/>"#,
+                        element,
+                        xml_escape(attempt.error.as_deref().unwrap_or("Test failed")),
+                        attempt.duration.as_secs_f64()
+                    )?;
+                }
+            }
+            if !res.stdout.is_empty() {
+                writeln!(
+                    xml,
+                    "      <system-out>{}</system-out>",
+                    xml_escape(&res.stdout)
+                )?;
+            }
+            if !res.stderr.is_empty() {
+                writeln!(
+                    xml,
+                    "      <system-err>{}</system-err>",
+                    xml_escape(&res.stderr)
+                )?;
+            }
+            writeln!(xml, "    </testcase>")?;
+        }
+        writeln!(xml, "  </testsuite>")?;
+    }
+    writeln!(xml, "</testsuites>")?;
+
+    fs::write(path, xml).with_context(|| format!("Failed to write JUnit report {:?}", path))
+}
+
+/// Styles of the HTML report, embedded so the file is self-contained
+const HTML_STYLE: &str = "\
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; }
+td, th { padding: 0.2em 0.8em; text-align: left; }
+.stats span { margin-right: 1.5em; font-weight: bold; }
+details { border: 1px solid #ddd; border-radius: 4px; margin: 0.4em 0; padding: 0.3em 0.6em; }
+summary { cursor: pointer; }
+.meta { color: #777; font-size: 0.9em; }
+.pass { color: #1a7f37; } .fail { color: #cf222e; } .skip { color: #9a6700; }
+pre { background: #f6f8fa; padding: 0.6em; overflow-x: auto; }
+.diff .add { background: #dafbe1; } .diff .del { background: #ffebe9; } .diff .hunk { color: #0969da; }
+";
+
+/// Writes a self-contained HTML report with summary stats and a collapsible section per test.
+/// Failed tests are expanded and show a colorized diff along with the captured output.
+pub fn write_html(
+    path: &Path,
+    results: &[TestResult],
+    metadata: &RunMetadata,
+    args: &Args,
+) -> Result<()> {
+    let passed = results.iter().filter(|r| r.passed).count();
+    let failed = results.iter().filter(|r| r.failed()).count();
+    let skipped = results.iter().filter(|r| r.skipped.is_some()).count();
+    let flaky = results.iter().filter(|r| r.flaky).count();
+    let duration: Duration = results.iter().map(|r| r.duration).sum();
+
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>")?;
+    writeln!(html, r#"<html lang="en"><head><meta charset="utf-8">"#)?;
+    writeln!(html, "<title>marco report</title>")?;
+    writeln!(html, "<style>{}</style></head><body>", HTML_STYLE)?;
+    writeln!(html, "<h1>marco report</h1>")?;
+    writeln!(
+        html,
+        r#"<p class="stats"><span>{} total</span><span class="pass">{} passed</span><span class="fail">{} failed</span><span class="skip">{} skipped</span><span class="skip">{} flaky</span><span>{:.2}s</span></p>"#,
+        results.len(),
+        passed,
+        failed,
+        skipped,
+        flaky,
+        duration.as_secs_f64()
+    )?;
+
+    writeln!(html, "<details><summary>Environment</summary><table>")?;
+    for (key, value) in &metadata.entries {
+        writeln!(
+            html,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            xml_escape(key),
+            xml_escape(value)
+        )?;
+    }
+    writeln!(html, "</table></details>")?;
+
+    writeln!(html, "<h2>Tests</h2>")?;
+    for res in results {
+        let (class, status) = if res.skipped.is_some() {
+            ("skip", "○")
+        } else if res.passed {
+            ("pass", "✔")
+        } else {
+            ("fail", "✘")
+        };
+        let open = if res.failed() { " open" } else { "" };
+        writeln!(
+            html,
+            r#"<details{}><summary><span class="{}">{}</span> {} <span class="meta">{} · {:.2}s</span></summary>"#,
+            open,
+            class,
+            status,
+            xml_escape(&res.name),
+            xml_escape(&res.file.to_string_lossy()),
+            res.duration.as_secs_f64()
+        )?;
+        if let Some(reason) = &res.skipped {
+            writeln!(html, "<p>Skipped: {}</p>", xml_escape(reason))?;
+        }
+        if let Some(err) = &res.error {
+            writeln!(html, r#"<p class="fail">{}</p>"#, xml_escape(err))?;
+        }
+        let mut facts = vec![];
+        if res.flaky {
+            facts.push(format!("Flaky: passed on attempt {}", res.attempts.len()));
+        } else if !res.attempts.is_empty() {
+            facts.push(format!("Attempts: {}", res.attempts.len()));
+        }
+        if let Some(code) = res.exit_code {
+            facts.push(format!("Exit code: {}", code));
+        }
+        if let Some(seed) = res.seed {
+            facts.push(format!("Seed: {}", seed));
+        }
+        if let Some(peak) = res.peak_memory {
+            facts.push(format!("Peak memory: {}", format_bytes(peak)));
+        }
+        if !facts.is_empty() {
+            writeln!(html, r#"<p class="meta">{}</p>"#, facts.join(" · "))?;
+        }
+        if let Some(repro) = &res.repro {
+            writeln!(html, "<p>Reproduce:</p><pre>{}</pre>", xml_escape(repro))?;
+        }
+        if res.failed() {
+            let diff = unified_diff(&res.actual, &res.expected, args.diff_algorithm.into());
+            if !diff.is_empty() {
+                writeln!(html, r#"<p>Diff:</p><pre class="diff">"#)?;
+                for line in diff.lines() {
+                    let class = if line.starts_with("@@") {
+                        "hunk"
+                    } else if line.starts_with('+') && !line.starts_with("+++") {
+                        "add"
+                    } else if line.starts_with('-') && !line.starts_with("---") {
+                        "del"
+                    } else {
+                        ""
+                    };
+                    writeln!(
+                        html,
+                        r#"<span class="{}">{}</span>"#,
+                        class,
+                        xml_escape(line)
+                    )?;
+                }
+                writeln!(html, "</pre>")?;
+            }
+        }
+        if !res.stdout.is_empty() {
+            writeln!(html, "<p>Stdout:</p><pre>{}</pre>", xml_escape(&res.stdout))?;
+        }
+        if !res.stderr.is_empty() {
+            writeln!(html, "<p>Stderr:</p><pre>{}</pre>", xml_escape(&res.stderr))?;
+        }
+        writeln!(html, "</details>")?;
+    }
+    writeln!(html, "</body></html>")?;
+
+    fs::write(path, html).with_context(|| format!("Failed to write HTML report {:?}", path))
+}
+
+/// Appends a Markdown summary of the run to `path`, e.g. the file in `GITHUB_STEP_SUMMARY`:
+/// a per-file results table, then the diff of every failed test in a collapsible section
+pub fn write_summary_md(path: &Path, results: &[TestResult], args: &Args) -> Result<()> {
+    let passed = results.iter().filter(|r| r.passed).count();
+    let failed = results.iter().filter(|r| r.failed()).count();
+    let skipped = results.iter().filter(|r| r.skipped.is_some()).count();
+
+    let mut md = String::new();
+    writeln!(md, "## marco results\n")?;
+    let icon = if failed > 0 { "❌" } else { "✅" };
+    writeln!(
+        md,
+        "{} **{} passed**, {} failed, {} skipped of {} tests\n",
+        icon,
+        passed,
+        failed,
+        skipped,
+        results.len()
+    )?;
+    let flaky: Vec<_> = results
+        .iter()
+        .filter(|r| r.flaky)
+        .map(|r| format!("`{}`", r.name.replace('`', "'")))
+        .collect();
+    if !flaky.is_empty() {
+        writeln!(
+            md,
+            "⚠️ {} flaky tests passed only after a retry: {}\n",
+            flaky.len(),
+            flaky.join(", ")
+        )?;
+    }
+
+    let mut by_file: BTreeMap<&PathBuf, Vec<&TestResult>> = BTreeMap::new();
+    for res in results {
+        by_file.entry(&res.file).or_default().push(res);
+    }
+    writeln!(md, "| File | Passed | Failed | Skipped | Duration |")?;
+    writeln!(md, "| --- | ---: | ---: | ---: | ---: |")?;
+    for (file, tests) in by_file {
+        let duration: Duration = tests.iter().map(|r| r.duration).sum();
+        writeln!(
+            md,
+            "| `{}` | {} | {} | {} | {:.2}s |",
+            file.to_string_lossy().replace('|', "\\|"),
+            tests.iter().filter(|r| r.passed).count(),
+            tests.iter().filter(|r| r.failed()).count(),
+            tests.iter().filter(|r| r.skipped.is_some()).count(),
+            duration.as_secs_f64()
+        )?;
+    }
+
+    for res in results.iter().filter(|r| r.failed()) {
+        writeln!(
+            md,
+            "\n<details><summary>❌ {} (in <code>{}</code>)</summary>\n",
+            xml_escape(&res.name),
+            xml_escape(&res.file.to_string_lossy())
+        )?;
+        if let Some(err) = &res.error {
+            writeln!(md, "{}\n", xml_escape(err))?;
+        }
+        let diff = unified_diff(&res.actual, &res.expected, args.diff_algorithm.into());
+        if !diff.is_empty() {
+            let fence = code_fence(&diff);
+            writeln!(md, "{}diff\n{}\n{}\n", fence, diff.trim_end(), fence)?;
+        }
+        writeln!(md, "</details>")?;
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open Markdown summary {:?}", path))?;
+    std::io::Write::write_all(&mut file, md.as_bytes())
+        .with_context(|| format!("Failed to write Markdown summary {:?}", path))
+}
+
+/// A backtick fence longer than any run of backticks in `content`, so it cannot be closed early
+fn code_fence(content: &str) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// Identifies a test across reports
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TestId {
+    pub file: PathBuf,
+    pub name: String,
+}
+
+impl TestId {
+    pub fn of(res: &TestResult) -> Self {
+        TestId {
+            file: res.file.clone(),
+            name: res.name.clone(),
+        }
+    }
+
+    pub fn of_test(test: &MarcoTestCase) -> Self {
+        TestId {
+            file: test.file.clone(),
+            name: test.header.name.clone(),
+        }
+    }
+}
+
+/// A report written by `--report-json`, as read back by `marco merge`
+#[derive(Debug, Deserialize)]
+pub struct JsonReport {
+    pub metadata: RunMetadata,
+    /// Every test the run collected, whether or not the report holds its result
+    pub collected: Vec<TestId>,
+    pub results: Vec<TestResult>,
+}
+
+impl JsonReport {
+    pub fn read(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read JSON report {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid JSON report {:?}", path))
+    }
+}
+
+/// Renders a JSON report with the metadata, the collected tests and every result
+pub fn json_report(
+    collected: &[TestId],
+    results: &[TestResult],
+    metadata: &RunMetadata,
+) -> Result<String> {
+    let report = serde_json::json!({
+        "metadata": metadata,
+        "collected": collected,
+        "results": results,
+    });
+    Ok(serde_json::to_string_pretty(&report)?)
+}
+
+/// Writes a JSON report rendered by `json_report` to `path`
+pub fn write_json(path: &Path, json: &str) -> Result<()> {
+    fs::write(path, json).with_context(|| format!("Failed to write JSON report {:?}", path))
+}
+
+/// The identities of collected tests, as recorded in JSON reports
+pub fn collected_ids(tests: &[MarcoTestCase]) -> Vec<TestId> {
+    tests.iter().map(TestId::of_test).collect()
+}
+
+/// Escapes text for use in XML (and HTML) content and attributes, dropping characters XML 1.0 forbids
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,287 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use anyhow::{Result, bail};
+
+use marco_core::runner::{CANCELLED_REASON, Observer};
+use marco_core::types::{MarcoTestCase, TestResult};
+
+use crate::cli::{Args, OutputFormat, ReporterKind};
+use crate::events;
+use crate::metadata::RunMetadata;
+use crate::report::{self, TestId};
+
+/// Where `--reporter junit` writes without `--report-junit`
+const DEFAULT_JUNIT_PATH: &str = "marco-junit.xml";
+/// Where `--reporter html` writes without `--report-html`
+const DEFAULT_HTML_PATH: &str = "marco-report.html";
+
+/// Everything reporters get once the run is over
+pub struct RunSummary<'a> {
+    /// Results in collection order
+    pub results: &'a [TestResult],
+    /// Number of tests not run because of `--filter`, `--tag`, `--last-failed`, ...
+    pub filtered_out: usize,
+    /// Every selected test, including the ones of other shards
+    pub collected: &'a [TestId],
+    pub metadata: &'a RunMetadata,
+    /// Index of the failure that stopped the run under `--fail-fast`
+    pub first_failure: Option<usize>,
+}
+
+/// Receives the events of a run. Tests run in parallel, so `test_started` and `test_finished`
+/// are called from several threads at once; `id` tells the tests apart.
+pub trait Reporter: Send + Sync {
+    /// Called once the tests are collected, before the first one starts, also for runs without tests
+    fn run_started(&self, _tests: &[MarcoTestCase], _metadata: &RunMetadata) {}
+
+    fn test_started(&self, _test: &MarcoTestCase, _id: usize) {}
+
+    fn test_finished(&self, _result: &TestResult, _id: usize) {}
+
+    /// Called with all results once every test finished, also for runs without tests
+    fn run_finished(&self, _run: &RunSummary) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The reporters of a run, each of which gets every event
+pub struct Reporters<'a>(Vec<Box<dyn Reporter + 'a>>);
+
+impl Reporters<'_> {
+    pub fn run_started(&self, tests: &[MarcoTestCase], metadata: &RunMetadata) {
+        for reporter in &self.0 {
+            reporter.run_started(tests, metadata);
+        }
+    }
+
+    pub fn run_finished(&self, run: &RunSummary) -> Result<()> {
+        self.0
+            .iter()
+            .try_for_each(|reporter| reporter.run_finished(run))
+    }
+}
+
+impl Observer for Reporters<'_> {
+    fn test_started(&self, test: &MarcoTestCase, id: usize) {
+        for reporter in &self.0 {
+            reporter.test_started(test, id);
+        }
+    }
+
+    fn test_finished(&self, result: &TestResult, id: usize) {
+        for reporter in &self.0 {
+            reporter.test_finished(result, id);
+        }
+    }
+}
+
+/// The reporters for a run: the one printing to stdout, picked with `--reporter` (console by
+/// default) or `--events`, and one per report file requested with `--reporter` or `--report-*`
+pub fn reporters(args: &Args) -> Result<Reporters<'_>> {
+    let chosen = |kind| args.reporter.contains(&kind);
+    let stdout = [
+        ReporterKind::Console,
+        ReporterKind::Json,
+        ReporterKind::Quiet,
+    ]
+    .into_iter()
+    .filter(|&kind| chosen(kind))
+    .count();
+    if stdout > 1 {
+        bail!("--reporter can only include one of console, json and quiet");
+    }
+    if args.events.is_some() && (chosen(ReporterKind::Console) || chosen(ReporterKind::Json)) {
+        bail!("--events cannot be combined with --reporter console or json");
+    }
+
+    let mut reporters: Vec<Box<dyn Reporter>> = vec![];
+    if args.events.is_some() {
+        reporters.push(Box::new(EventsReporter { args }));
+    } else if chosen(ReporterKind::Json) {
+        reporters.push(Box::new(JsonReporter));
+    } else if !chosen(ReporterKind::Quiet) {
+        reporters.push(Box::new(ConsoleReporter {
+            args,
+            started: OnceLock::new(),
+        }));
+    }
+    reporters.extend(
+        file_reporters(args)
+            .into_iter()
+            .map(|r| Box::new(r) as Box<dyn Reporter>),
+    );
+    Ok(Reporters(reporters))
+}
+
+/// Reporters for the report files requested with `--reporter` or `--report-*`
+pub fn file_reporters(args: &Args) -> Vec<FileReporter<'_>> {
+    let or_default = |path: &Option<PathBuf>, kind, default: &str| {
+        path.clone().or_else(|| {
+            args.reporter
+                .contains(&kind)
+                .then(|| PathBuf::from(default))
+        })
+    };
+    let files = [
+        (FileReport::Log, args.log_file.clone()),
+        (
+            FileReport::Junit,
+            or_default(&args.report_junit, ReporterKind::Junit, DEFAULT_JUNIT_PATH),
+        ),
+        (
+            FileReport::Html,
+            or_default(&args.report_html, ReporterKind::Html, DEFAULT_HTML_PATH),
+        ),
+        (FileReport::SummaryMd, args.summary_md.clone()),
+        (FileReport::Json, args.report_json.clone()),
+    ];
+    files
+        .into_iter()
+        .filter_map(|(kind, path)| {
+            Some(FileReporter {
+                kind,
+                path: path?,
+                args,
+            })
+        })
+        .collect()
+}
+
+/// Prints live progress and the results in the `--format` chosen for the console
+pub struct ConsoleReporter<'a> {
+    args: &'a Args,
+    /// When the run started, for formats that report its duration
+    started: OnceLock<Instant>,
+}
+
+impl Reporter for ConsoleReporter<'_> {
+    fn run_started(&self, tests: &[MarcoTestCase], metadata: &RunMetadata) {
+        self.started.get_or_init(Instant::now);
+        if self.args.verbose > 0 && !self.args.machine_output() {
+            report::print_metadata(metadata);
+        }
+        report::print_run_started(tests.len(), self.args);
+    }
+
+    fn test_started(&self, test: &MarcoTestCase, id: usize) {
+        report::print_test_started(test, id, self.args);
+    }
+
+    fn test_finished(&self, result: &TestResult, id: usize) {
+        report::print_test_finished(result, id, self.args);
+    }
+
+    fn run_finished(&self, run: &RunSummary) -> Result<()> {
+        let args = self.args;
+        // A run without tests was already explained by a progress message
+        if run.results.is_empty() && args.format == OutputFormat::Human {
+            return Ok(());
+        }
+        match run.first_failure {
+            Some(i) if !args.machine_output() => {
+                let not_run = run
+                    .results
+                    .iter()
+                    .filter(|r| r.skipped.as_deref() == Some(CANCELLED_REASON))
+                    .count();
+                println!();
+                report::print_result(&run.results[i], args);
+                println!(
+                    "\nStopped at the first failure (--fail-fast); {} tests were not run",
+                    not_run
+                );
+            }
+            _ => {
+                let elapsed = self.started.get().map(Instant::elapsed).unwrap_or_default();
+                report::print_results(run.results, run.filtered_out, elapsed, args)
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Streams `--events ndjson` to stdout
+pub struct EventsReporter<'a> {
+    args: &'a Args,
+}
+
+impl Reporter for EventsReporter<'_> {
+    fn test_started(&self, test: &MarcoTestCase, id: usize) {
+        events::emit(
+            self.args,
+            "test_started",
+            serde_json::json!({ "id": id, "name": &test.header.name, "file": &test.file }),
+        );
+    }
+
+    fn test_finished(&self, result: &TestResult, id: usize) {
+        events::emit(
+            self.args,
+            "test_finished",
+            serde_json::json!({ "id": id, "status": result.status(), "result": result }),
+        );
+    }
+
+    fn run_finished(&self, run: &RunSummary) -> Result<()> {
+        let results = run.results;
+        events::emit(
+            self.args,
+            "run_finished",
+            serde_json::json!({
+                "passed": results.iter().filter(|r| r.passed).count(),
+                "failed": results.iter().filter(|r| r.failed()).count(),
+                "skipped": results.iter().filter(|r| r.skipped.is_some()).count(),
+                "flaky": results.iter().filter(|r| r.flaky).count(),
+                "total": results.len(),
+                "filtered_out": run.filtered_out,
+            }),
+        );
+        Ok(())
+    }
+}
+
+/// Prints the JSON report of `--report-json` to stdout once the run is over
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn run_finished(&self, run: &RunSummary) -> Result<()> {
+        let json = report::json_report(run.collected, run.results, run.metadata)?;
+        println!("{}", json);
+        Ok(())
+    }
+}
+
+/// A report file written once the run is over
+#[derive(Debug, Clone, Copy)]
+pub enum FileReport {
+    Log,
+    Junit,
+    Html,
+    SummaryMd,
+    Json,
+}
+
+/// Writes one report file
+pub struct FileReporter<'a> {
+    kind: FileReport,
+    path: PathBuf,
+    args: &'a Args,
+}
+
+impl Reporter for FileReporter<'_> {
+    fn run_finished(&self, run: &RunSummary) -> Result<()> {
+        let (path, args) = (&self.path, self.args);
+        match self.kind {
+            FileReport::Log => report::write_log(path, run.results, run.metadata, args),
+            FileReport::Junit => report::write_junit(path, run.results, run.metadata, args),
+            FileReport::Html => report::write_html(path, run.results, run.metadata, args),
+            FileReport::SummaryMd => report::write_summary_md(path, run.results, args),
+            FileReport::Json => report::write_json(
+                path,
+                &report::json_report(run.collected, run.results, run.metadata)?,
+            ),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use marco_core::normalize::Normalizer;
+use marco_core::parser::replace_code_blocks;
+use marco_core::types::{MarcoTestCase, TestResult};
+use marco_core::util::{normalize_newlines, strip_ansi};
+
+use crate::cli::Args;
+use crate::report::print_result;
+
+/// What the user decided for a failed test
+enum Decision {
+    Accept,
+    Reject,
+    Skip,
+    Quit,
+}
+
+/// Walks through every test that failed on its output, showing the diff and asking whether to
+/// accept the actual output. Accepted outputs are written back into the Expected Output blocks
+/// and their results marked as passed. `results` must line up with `tests`.
+pub fn review_failures(
+    tests: &[MarcoTestCase],
+    results: &mut [TestResult],
+    args: &Args,
+) -> Result<()> {
+    let reviewable: Vec<_> = (0..results.len())
+        .filter(|&i| is_reviewable(&tests[i], &results[i]))
+        .collect();
+    if reviewable.is_empty() {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        eprintln!("Warning: --interactive needs a terminal on stdin; skipping review");
+        return Ok(());
+    }
+
+    println!(
+        "\nReviewing {} tests with mismatched output",
+        reviewable.len()
+    );
+    let mut updates = Updates::new();
+    let mut accepted = vec![];
+    let (mut rejected, mut skipped) = (0, 0);
+    for (n, &i) in reviewable.iter().enumerate() {
+        let (test, res) = (&tests[i], &results[i]);
+        println!();
+        print_result(res, args);
+        match prompt(n + 1, reviewable.len())? {
+            Decision::Accept => {
+                add_update(&mut updates, test, res, args);
+                accepted.push(i);
+            }
+            Decision::Reject => rejected += 1,
+            Decision::Skip => skipped += 1,
+            Decision::Quit => {
+                skipped += reviewable.len() - n;
+                break;
+            }
+        }
+    }
+
+    write_back(
+        results,
+        updates,
+        &accepted,
+        "Expected Output updated interactively",
+    )?;
+    println!(
+        "\nReview: {} accepted, {} rejected, {} skipped",
+        accepted.len(),
+        rejected,
+        skipped
+    );
+    Ok(())
+}
+
+/// Writes the actual output of every test that failed on its output into its Expected Output
+/// block without asking, as `marco update` does, and marks those tests as passed
+pub fn update_failures(
+    tests: &[MarcoTestCase],
+    results: &mut [TestResult],
+    args: &Args,
+) -> Result<()> {
+    let mut updates = Updates::new();
+    let mut accepted = vec![];
+    for i in 0..results.len() {
+        if is_reviewable(&tests[i], &results[i]) {
+            add_update(&mut updates, &tests[i], &results[i], args);
+            accepted.push(i);
+        }
+    }
+    write_back(results, updates, &accepted, "Expected Output updated")?;
+    println!(
+        "\nUpdated the Expected Output of {} tests in {} files",
+        accepted.len(),
+        accepted
+            .iter()
+            .map(|&i| &tests[i].file)
+            .collect::<BTreeSet<_>>()
+            .len()
+    );
+    Ok(())
+}
+
+/// New Expected Output block contents by file and block index
+type Updates = BTreeMap<PathBuf, BTreeMap<usize, String>>;
+
+fn add_update(updates: &mut Updates, test: &MarcoTestCase, res: &TestResult, args: &Args) {
+    let Some(block) = test.expected_block else {
+        return;
+    };
+    // Variants of a `runners` test share one block; the first accepted output wins
+    updates
+        .entry(test.file.clone())
+        .or_default()
+        .entry(block)
+        .or_insert_with(|| actual_output(test, res, args));
+}
+
+/// Rewrites the updated blocks in their files and marks the accepted results as passed
+fn write_back(
+    results: &mut [TestResult],
+    updates: Updates,
+    accepted: &[usize],
+    note: &str,
+) -> Result<()> {
+    for (file, blocks) in &updates {
+        let src =
+            fs::read_to_string(file).with_context(|| format!("Failed to read file {:?}", file))?;
+        let updated = replace_code_blocks(&src, blocks)
+            .with_context(|| format!("Failed to update expected output in {:?}", file))?;
+        fs::write(file, updated).with_context(|| format!("Failed to write file {:?}", file))?;
+    }
+    for &i in accepted {
+        let res = &mut results[i];
+        res.passed = true;
+        res.error = None;
+        res.warnings.push(note.to_string());
+    }
+    Ok(())
+}
+
+/// Whether a failed test can be fixed by rewriting its Expected Output block
+fn is_reviewable(test: &MarcoTestCase, res: &TestResult) -> bool {
+    // Output written into a regex block would no longer match itself, and text does not belong
+    // in a hex or base64 block
+    res.failed()
+        && test.expected_block.is_some()
+        && !test.expected_regex
+        && test.expected_bytes.is_none()
+        && res.error.as_deref() == Some("Output did not match expected")
+}
+
+/// The output to store in the Expected Output block, as the runner compared it
+fn actual_output(test: &MarcoTestCase, res: &TestResult, args: &Args) -> String {
+    // A failed checksum comparison already holds the digest of the raw output
+    if test.expected_sha256.is_some() {
+        return res.actual.clone();
+    }
+    let output = if test.header.expect_failure && test.expected_stderr.is_none() {
+        [res.stdout.trim(), res.stderr.trim()]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        res.stdout.trim().to_string()
+    };
+    // Store the output as it is compared, e.g. with temp paths already scrubbed
+    let output = if test.header.strip_ansi.unwrap_or(args.strip_ansi) {
+        strip_ansi(&output).into_owned()
+    } else {
+        output
+    };
+    let output = match Normalizer::new(&test.header.normalize) {
+        Ok(normalizer) => normalizer.apply(&output),
+        Err(_) => output,
+    };
+    normalize_newlines(&output)
+}
+
+/// Asks for a decision until a valid answer is given; end of input quits the review
+fn prompt(n: usize, total: usize) -> Result<Decision> {
+    loop {
+        print!(
+            "[{}/{}] Accept actual output? [a]ccept, [r]eject, [s]kip, [q]uit: ",
+            n, total
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(Decision::Quit);
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "a" | "accept" => return Ok(Decision::Accept),
+            "r" | "reject" => return Ok(Decision::Reject),
+            "s" | "skip" | "" => return Ok(Decision::Skip),
+            "q" | "quit" => return Ok(Decision::Quit),
+            _ => println!("Please answer a, r, s or q."),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+
+use crate::cli::{Args, NewArgs};
+
+/// Runner used in new test files when `--runner` is not given
+const DEFAULT_RUNNER: &str = "cat";
+
+/// Writes a skeleton test file named after the test into `--dir` and returns its path.
+/// Existing files are never overwritten.
+pub fn new_test_file(new: &NewArgs, args: &Args) -> Result<PathBuf> {
+    let slug = slugify(&new.name);
+    if slug.is_empty() {
+        bail!(
+            "Test name {:?} has no letters or digits to name the file after",
+            new.name
+        );
+    }
+    let path = new.dir.join(format!("{}.marco.md", slug));
+    if path.exists() {
+        bail!("File {:?} already exists", path);
+    }
+
+    let mut header = format!("name: {}\n", yaml_scalar(&new.name));
+    if let Some(author) = &new.author {
+        header.push_str(&format!("author: {}\n", yaml_scalar(author)));
+    }
+    let runner = args.runner.as_deref().unwrap_or(DEFAULT_RUNNER);
+    header.push_str(&format!("runner: {}\n", yaml_scalar(runner)));
+    let contents = format!(
+        "---\n{}---\n\n## First test\n\nInput:\n\n```\nHello, world!\n```\n\nExpected Output:\n\n```\nHello, world!\n```\n",
+        header
+    );
+
+    fs::create_dir_all(&new.dir)
+        .with_context(|| format!("Failed to create directory {:?}", new.dir))?;
+    fs::write(&path, contents).with_context(|| format!("Failed to write file {:?}", path))?;
+    Ok(path)
+}
+
+/// Lowercase file name stem with runs of other characters turned into single dashes
+fn slugify(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Quotes a YAML value only when it would otherwise not read back as the same string
+fn yaml_scalar(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.trim() == s
+        && !s.contains([':', '#', '{', '}', '[', ']', ',', '"', '\''])
+        && !s.starts_with(['&', '*', '!', '|', '>', '%', '@', '`', '-', '?'])
+        && s.parse::<f64>().is_err()
+        && !["true", "false", "null", "~"].contains(&s.to_lowercase().as_str());
+    if plain {
+        s.to_string()
+    } else {
+        serde_json::to_string(s).unwrap_or_default()
+    }
+}
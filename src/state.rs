@@ -0,0 +1,55 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use marco_core::types::TestResult;
+
+use crate::report::TestId;
+
+/// Where outcomes are kept between runs, relative to the working directory
+pub const STATE_FILE: &str = ".marco/state.json";
+
+/// Outcomes of earlier runs, used by `--last-failed` and `--failed-first`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Tests whose latest run failed
+    pub failed: BTreeSet<TestId>,
+}
+
+impl State {
+    /// Reads the state file, or returns an empty state if there is none yet
+    pub fn load() -> Result<State> {
+        let path = Path::new(STATE_FILE);
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read state file {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid state file {:?}", path))
+    }
+
+    /// Records the outcomes of a run. Tests that did not run (skipped or not selected)
+    /// keep their earlier outcome, so `--last-failed` works through failures one run at a time.
+    pub fn update(&mut self, results: &[TestResult]) {
+        for res in results {
+            if res.failed() {
+                self.failed.insert(TestId::of(res));
+            } else if res.passed {
+                self.failed.remove(&TestId::of(res));
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Path::new(STATE_FILE);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create state directory {:?}", dir))?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).with_context(|| format!("Failed to write state file {:?}", path))
+    }
+}
@@ -0,0 +1,13 @@
+# A tiny calculator REPL that logs more to stderr than a pipe holds before it is ready
+i=0
+while [ "$i" -lt 5000 ]; do
+    echo "calc: loading table $i of 5000" >&2
+    i=$((i + 1))
+done
+echo "ready"
+while read -r line; do
+    case "$line" in
+        quit) echo "bye"; exit 0 ;;
+        *) echo "$(($line))" ;;
+    esac
+done
@@ -0,0 +1,6 @@
+# A REPL that prints a prompt, then evaluates each line it reads and prompts again
+printf '> '
+while IFS= read -r line; do
+    eval "$line"
+    printf '> '
+done
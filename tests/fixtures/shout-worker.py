@@ -0,0 +1,32 @@
+# A runner_mode: worker runner: answers each JSON request line with a JSON response line.
+# Each worker logs its pid to shout-worker.log when it starts, which `--reset` clears and
+# `--started-once` checks, so a test file can tell that its tests shared one worker.
+import json
+import os
+import sys
+
+LOG = "shout-worker.log"
+
+if sys.argv[1:] == ["--reset"]:
+    if os.path.exists(LOG):
+        os.remove(LOG)
+    sys.exit(0)
+if sys.argv[1:] == ["--started-once"]:
+    with open(LOG) as log:
+        pids = log.read().split()
+    os.remove(LOG)
+    if len(pids) != 1:
+        sys.exit("expected one worker, but %d started: %s" % (len(pids), " ".join(pids)))
+    sys.exit(0)
+
+with open(LOG, "a") as log:
+    log.write("%d\n" % os.getpid())
+
+for line in sys.stdin:
+    request = json.loads(line)
+    text = request["input"].strip()
+    if text:
+        response = {"id": request["id"], "stdout": text.upper() + "\n"}
+    else:
+        response = {"id": request["id"], "stderr": "nothing to shout\n", "exit_code": 1}
+    print(json.dumps(response), flush=True)
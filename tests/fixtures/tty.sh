@@ -0,0 +1,4 @@
+if [ -t 1 ]; then echo "stdout is a terminal"; else echo "stdout is a pipe"; fi
+echo "stderr goes to the terminal too" >&2
+read -r line
+echo "read: $line"
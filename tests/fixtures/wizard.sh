@@ -0,0 +1,5 @@
+echo "What is your name?"
+read -r name
+echo "Hello, $name! How old are you?"
+read -r age
+echo "$name is $age."